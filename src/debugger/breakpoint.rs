@@ -0,0 +1,56 @@
+use std::fmt;
+
+use dmg::Dmg;
+use super::expr::Expr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    // None matches the address in any bank
+    pub bank: Option<usize>,
+    pub addr: u16,
+}
+
+impl Location {
+    pub fn matches(&self, dmg: &Dmg, pc: u16) -> bool {
+        if pc != self.addr {
+            return false;
+        }
+        match self.bank {
+            None => true,
+            Some(bank) => bank == current_bank(dmg, pc)
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.addr),
+            None => write!(f, "{:04X}", self.addr)
+        }
+    }
+}
+
+pub struct Breakpoint {
+    pub id: usize,
+    pub location: Location,
+    // only stops when this evaluates to non-zero
+    pub condition: Option<Expr>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.condition {
+            Some(ref condition) => write!(f, "{} if {}", self.location, condition),
+            None => write!(f, "{}", self.location)
+        }
+    }
+}
+
+// ROM bank an address is read from; only the switchable area isn't bank 0
+pub fn current_bank(dmg: &Dmg, addr: u16) -> usize {
+    match addr {
+        0x4000 ..= 0x7FFF => dmg.cart().rom_bank(),
+        _ => 0
+    }
+}
@@ -1,16 +1,72 @@
 use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::str::{self, FromStr};
 
-use nom::{IResult, eof, space, digit};
+use nom::{IResult, eof, space, digit, hex_digit};
+
+use dmg::cpu::{Reg8, Reg16};
+use dmg::watch::Access;
+
+use super::breakpoint::Location;
+use super::expr::{BinaryOp, Expr, Flag, UnaryOp};
+use super::trace::TraceOptions;
 // use nom::IResult::*;
 
-#[derive(Debug, Clone, Copy)]
+// a binary operator and the spaces around it
+macro_rules! operator (
+    ($i:expr, $($submac:ident!( $($args:tt)* ) => $op:path)|+) => (
+        delimited!($i,
+                   opt!(space),
+                   alt_complete!($($submac!($($args)*) => { |_| $op })|+),
+                   opt!(space))
+    );
+);
+
+#[derive(Debug, Clone)]
 pub enum Command {
     Step(usize),
+    Continue,
+    Next,
+    Finish,
+    Until(Address),
+    Frame,
+    Line,
+    Break(Address, Option<Expr>),
+    // access, first and last address, value to match
+    Watch(Access, Address, Option<Address>, Option<u8>),
+    // None deletes every breakpoint
+    Delete(Option<usize>),
+    List,
+    // start address (PC if omitted) and instruction count
+    Disasm(Option<Address>, usize),
+    Regs,
+    Backtrace,
+    // how many of the last instructions run to show
+    History(usize),
+    Set(Register, u16),
+    // address and byte count
+    Examine(Address, usize),
+    Poke(Address, u8),
+    Io,
+    Print(Expr),
+    // file and filters
+    Trace(String, TraceOptions),
+    TraceOff,
     Exit,
     Repeat,
 }
 
+// a label or a [bank:]address, resolved when the command runs since
+// labels can shadow hex numbers ("Add", "beef")
+#[derive(Debug, Clone)]
+pub struct Address(pub String);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Register {
+    R8(Reg8),
+    R16(Reg16),
+}
+
 impl FromStr for Command {
     type Err = Cow<'static, str>;
 
@@ -26,7 +82,27 @@ named!(
     command<Command>,
     chain!(
         c: alt_complete!(
+            set |
             step |
+            continue_ |
+            next |
+            finish |
+            until |
+            frame |
+            line |
+            break_ |
+            watch |
+            disasm |
+            delete |
+            list |
+            regs |
+            backtrace |
+            history |
+            examine |
+            poke |
+            print |
+            io |
+            trace |
             exit |
             repeat) ~
             eof,
@@ -39,6 +115,241 @@ named!(
             count: opt!(preceded!(space, usize_parser)),
         || Command::Step(count.unwrap_or(1))));
 
+named!(
+    continue_<Command>,
+    map!(
+        alt_complete!(tag!("continue") | tag!("c")),
+        |_| Command::Continue));
+
+named!(
+    next<Command>,
+    map!(
+        alt_complete!(tag!("next") | tag!("n")),
+        |_| Command::Next));
+
+named!(
+    finish<Command>,
+    map!(
+        alt_complete!(tag!("finish") | tag!("fin")),
+        |_| Command::Finish));
+
+named!(
+    until<Command>,
+    chain!(
+        alt_complete!(tag!("until") | tag!("u")) ~
+            space ~
+            addr: address,
+        || Command::Until(addr)));
+
+named!(
+    frame<Command>,
+    map!(
+        tag!("frame"),
+        |_| Command::Frame));
+
+named!(
+    line<Command>,
+    map!(
+        tag!("line"),
+        |_| Command::Line));
+
+named!(
+    break_<Command>,
+    chain!(
+        alt_complete!(tag!("break") | tag!("b")) ~
+            space ~
+            addr: address ~
+            condition: opt!(complete!(preceded!(
+                delimited!(space, tag!("if"), space),
+                expr))),
+        || Command::Break(addr, condition)));
+
+named!(
+    watch<Command>,
+    chain!(
+        tag!("watch") ~
+            access: opt!(complete!(terminated!(preceded!(space, access), peek!(space)))) ~
+            space ~
+            start: address ~
+            end: opt!(complete!(preceded!(tag!("-"), address))) ~
+            value: opt!(complete!(preceded!(space, value_condition))),
+        || Command::Watch(access.unwrap_or(Access::Write), start, end, value)));
+
+named!(
+    access<Access>,
+    alt_complete!(
+        tag!("rw") => { |_| Access::ReadWrite } |
+        tag!("r") => { |_| Access::Read } |
+        tag!("w") => { |_| Access::Write }));
+
+named!(
+    value_condition<u8>,
+    chain!(
+        tag!("if") ~
+            space ~
+            tag!("value") ~
+            opt!(space) ~
+            tag!("==") ~
+            opt!(space) ~
+            value: hex_u8,
+        || value));
+
+named!(
+    disasm<Command>,
+    chain!(
+        tag!("disasm") ~
+            addr: opt!(complete!(preceded!(space, address))) ~
+            count: opt!(complete!(preceded!(space, usize_parser))),
+        || Command::Disasm(addr, count.unwrap_or(10))));
+
+named!(
+    delete<Command>,
+    chain!(
+        alt_complete!(tag!("delete") | tag!("d")) ~
+            id: opt!(preceded!(space, usize_parser)),
+        || Command::Delete(id)));
+
+named!(
+    list<Command>,
+    map!(
+        alt_complete!(tag!("list") | tag!("l")),
+        |_| Command::List));
+
+named!(
+    regs<Command>,
+    map!(
+        alt_complete!(tag!("regs") | tag!("r")),
+        |_| Command::Regs));
+
+named!(
+    backtrace<Command>,
+    map!(
+        alt_complete!(tag!("backtrace") | tag!("bt")),
+        |_| Command::Backtrace));
+
+named!(
+    history<Command>,
+    chain!(
+        tag!("history") ~
+            count: opt!(complete!(preceded!(space, usize_parser))),
+        || Command::History(count.unwrap_or(16))));
+
+named!(
+    set<Command>,
+    chain!(
+        tag!("set") ~
+            space ~
+            reg: register ~
+            space ~
+            value: hex_u16,
+        || Command::Set(reg, value)));
+
+// 16-bit names first so "af" isn't read as "a"
+named!(
+    register<Register>,
+    alt_complete!(
+        tag!("af") => { |_| Register::R16(Reg16::AF) } |
+        tag!("bc") => { |_| Register::R16(Reg16::BC) } |
+        tag!("de") => { |_| Register::R16(Reg16::DE) } |
+        tag!("hl") => { |_| Register::R16(Reg16::HL) } |
+        tag!("sp") => { |_| Register::R16(Reg16::SP) } |
+        tag!("pc") => { |_| Register::R16(Reg16::PC) } |
+        tag!("a") => { |_| Register::R8(Reg8::A) } |
+        tag!("b") => { |_| Register::R8(Reg8::B) } |
+        tag!("c") => { |_| Register::R8(Reg8::C) } |
+        tag!("d") => { |_| Register::R8(Reg8::D) } |
+        tag!("e") => { |_| Register::R8(Reg8::E) } |
+        tag!("f") => { |_| Register::R8(Reg8::F) } |
+        tag!("h") => { |_| Register::R8(Reg8::H) } |
+        tag!("l") => { |_| Register::R8(Reg8::L) }));
+
+named!(
+    examine<Command>,
+    chain!(
+        tag!("x") ~
+            count: opt!(complete!(preceded!(tag!("/"), usize_parser))) ~
+            space ~
+            addr: address,
+        || Command::Examine(addr, count.unwrap_or(16))));
+
+named!(
+    poke<Command>,
+    chain!(
+        tag!("poke") ~
+            space ~
+            addr: address ~
+            space ~
+            value: hex_u8,
+        || Command::Poke(addr, value)));
+
+// after poke, so "p" doesn't swallow it
+named!(
+    print<Command>,
+    chain!(
+        alt_complete!(tag!("print") | tag!("p")) ~
+            space ~
+            expr: expr,
+        || Command::Print(expr)));
+
+named!(
+    io<Command>,
+    map!(
+        tag!("io"),
+        |_| Command::Io));
+
+named!(
+    trace<Command>,
+    chain!(
+        tag!("trace") ~
+            space ~
+            command: alt_complete!(
+                chain!(tag!("off") ~ eof, || Command::TraceOff) |
+                chain!(
+                    path: map_res!(is_not!(" \t"), str::from_utf8) ~
+                        options: many0!(complete!(preceded!(space, trace_option))),
+                    || Command::Trace(path.into(), trace_options(options)))),
+        || command));
+
+#[derive(Debug, Clone, Copy)]
+enum TraceOption {
+    Range(u16, u16),
+    Bank(usize),
+    Ly,
+    Cycles,
+}
+
+// `pc START-END`, `bank BB`, `ly` and `cycles`
+named!(
+    trace_option<TraceOption>,
+    alt_complete!(
+        chain!(
+            tag!("pc") ~
+                space ~
+                start: hex_u16 ~
+                tag!("-") ~
+                end: hex_u16,
+            || TraceOption::Range(start, end)) |
+        chain!(
+            tag!("bank") ~
+                space ~
+                bank: hex_usize,
+            || TraceOption::Bank(bank)) |
+        tag!("ly") => { |_| TraceOption::Ly } |
+        tag!("cycles") => { |_| TraceOption::Cycles }));
+
+fn trace_options(options: Vec<TraceOption>) -> TraceOptions {
+    let mut trace_options = TraceOptions::default();
+    for option in options {
+        match option {
+            TraceOption::Range(start, end) => trace_options.range = Some((start, end)),
+            TraceOption::Bank(bank) => trace_options.bank = Some(bank),
+            TraceOption::Ly => trace_options.ly = true,
+            TraceOption::Cycles => trace_options.cycles = true,
+        }
+    }
+    trace_options
+}
+
 named!(
     exit<Command>,
     map!(
@@ -56,3 +367,232 @@ named!(
             digit,
             str::from_utf8),
         FromStr::from_str));
+
+// parse a hex location typed in place of a label
+pub fn parse_location(s: &str) -> Option<Location> {
+    match terminated!(s.as_bytes(), location, eof) {
+        IResult::Done(_, location) => Some(location),
+        _ => None
+    }
+}
+
+pub fn parse_expr(s: &str) -> Result<Expr, String> {
+    match terminated!(s.trim().as_bytes(), expr, eof) {
+        IResult::Done(_, expr) => Ok(expr),
+        _ => Err(format!("Unable to parse expression: {}", s))
+    }
+}
+
+pub fn parse_register(s: &str) -> Option<Register> {
+    match terminated!(s.as_bytes(), register, eof) {
+        IResult::Done(_, reg) => Some(reg),
+        _ => None
+    }
+}
+
+named!(
+    address<Address>,
+    map!(
+        map_res!(
+            take_while1!(is_address_char),
+            str::from_utf8),
+        |s: &str| Address(s.into())));
+
+// RGBDS label characters, plus the hex prefixes and bank separator
+fn is_address_char(c: u8) -> bool {
+    (c as char).is_ascii_alphanumeric() || b"_.@#$:".contains(&c)
+}
+
+// addresses and banks are hex, with an optional 0x or $ prefix
+named!(
+    location<Location>,
+    chain!(
+        bank: opt!(complete!(terminated!(hex_usize, tag!(":")))) ~
+            addr: hex_u16,
+        || Location { bank: bank, addr: addr }));
+
+named!(
+    hex_u16<u16>,
+    map_opt!(hex_number, |n| u16::try_from(n).ok()));
+
+named!(
+    hex_u8<u8>,
+    map_opt!(hex_number, |n| u8::try_from(n).ok()));
+
+named!(
+    hex_usize<usize>,
+    map_opt!(hex_number, |n| usize::try_from(n).ok()));
+
+named!(
+    hex_number<u64>,
+    preceded!(
+        opt!(alt_complete!(tag!("0x") | tag!("$"))),
+        map_res!(
+            map_res!(
+                hex_digit,
+                str::from_utf8),
+            |s| u64::from_str_radix(s, 16))));
+
+// Expressions, loosest binding first. Numbers are decimal unless prefixed
+// with 0x or $, since a bare "a" or "c" names a register.
+named!(
+    expr<Expr>,
+    chain!(
+        first: and_expr ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("||") => BinaryOp::Or),
+                and_expr))),
+        || fold(first, rest)));
+
+named!(
+    and_expr<Expr>,
+    chain!(
+        first: comparison ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("&&") => BinaryOp::And),
+                comparison))),
+        || fold(first, rest)));
+
+// two-character operators first so "<=" isn't read as "<"
+named!(
+    comparison<Expr>,
+    chain!(
+        first: bit_or ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("==") => BinaryOp::Eq |
+                          tag!("!=") => BinaryOp::Ne |
+                          tag!("<=") => BinaryOp::Le |
+                          tag!(">=") => BinaryOp::Ge |
+                          tag!("<") => BinaryOp::Lt |
+                          tag!(">") => BinaryOp::Gt),
+                bit_or))),
+        || fold(first, rest)));
+
+// a lone "|" or "&" from "||" or "&&" fails on its missing operand and
+// is left for the looser level
+named!(
+    bit_or<Expr>,
+    chain!(
+        first: bit_and ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("|") => BinaryOp::BitOr |
+                          tag!("^") => BinaryOp::BitXor),
+                bit_and))),
+        || fold(first, rest)));
+
+named!(
+    bit_and<Expr>,
+    chain!(
+        first: sum ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("&") => BinaryOp::BitAnd),
+                sum))),
+        || fold(first, rest)));
+
+named!(
+    sum<Expr>,
+    chain!(
+        first: product ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("+") => BinaryOp::Add |
+                          tag!("-") => BinaryOp::Sub),
+                product))),
+        || fold(first, rest)));
+
+named!(
+    product<Expr>,
+    chain!(
+        first: unary ~
+            rest: many0!(complete!(pair!(
+                operator!(tag!("*") => BinaryOp::Mul |
+                          tag!("/") => BinaryOp::Div |
+                          tag!("%") => BinaryOp::Rem),
+                unary))),
+        || fold(first, rest)));
+
+named!(
+    unary<Expr>,
+    alt_complete!(
+        chain!(
+            op: alt_complete!(
+                tag!("-") => { |_| UnaryOp::Neg } |
+                tag!("!") => { |_| UnaryOp::Not } |
+                tag!("~") => { |_| UnaryOp::Complement }) ~
+                opt!(space) ~
+                operand: unary,
+            || Expr::Unary(op, Box::new(operand))) |
+        primary));
+
+named!(
+    primary<Expr>,
+    alt_complete!(
+        map!(hex_literal, Expr::Number) |
+        map_res!(
+            map_res!(
+                digit,
+                str::from_utf8),
+            |s: &str| s.parse().map(Expr::Number)) |
+        map!(
+            delimited!(
+                terminated!(tag!("["), opt!(space)),
+                expr,
+                preceded!(opt!(space), tag!("]"))),
+            |addr| Expr::Deref(Box::new(addr))) |
+        delimited!(
+            terminated!(tag!("("), opt!(space)),
+            expr,
+            preceded!(opt!(space), tag!(")"))) |
+        identifier));
+
+named!(
+    identifier<Expr>,
+    map!(
+        map_res!(
+            take_while1!(is_identifier_char),
+            str::from_utf8),
+        name_expr));
+
+named!(
+    hex_literal<i64>,
+    preceded!(
+        alt_complete!(tag!("0x") | tag!("$")),
+        map_res!(
+            map_res!(
+                hex_digit,
+                str::from_utf8),
+            |s| i64::from_str_radix(s, 16))));
+
+fn is_identifier_char(c: u8) -> bool {
+    (c as char).is_ascii_alphanumeric() || b"_.@#".contains(&c)
+}
+
+// registers and flags, anything else is a label
+fn name_expr(name: &str) -> Expr {
+    match name {
+        "a" => Expr::Register(Register::R8(Reg8::A)),
+        "b" => Expr::Register(Register::R8(Reg8::B)),
+        "c" => Expr::Register(Register::R8(Reg8::C)),
+        "d" => Expr::Register(Register::R8(Reg8::D)),
+        "e" => Expr::Register(Register::R8(Reg8::E)),
+        "f" => Expr::Register(Register::R8(Reg8::F)),
+        "h" => Expr::Register(Register::R8(Reg8::H)),
+        "l" => Expr::Register(Register::R8(Reg8::L)),
+        "af" => Expr::Register(Register::R16(Reg16::AF)),
+        "bc" => Expr::Register(Register::R16(Reg16::BC)),
+        "de" => Expr::Register(Register::R16(Reg16::DE)),
+        "hl" => Expr::Register(Register::R16(Reg16::HL)),
+        "sp" => Expr::Register(Register::R16(Reg16::SP)),
+        "pc" => Expr::Register(Register::R16(Reg16::PC)),
+        "zf" => Expr::Flag(Flag::Z),
+        "nf" => Expr::Flag(Flag::N),
+        "hf" => Expr::Flag(Flag::H),
+        "cf" => Expr::Flag(Flag::C),
+        _ => Expr::Symbol(name.into())
+    }
+}
+
+fn fold(first: Expr, rest: Vec<(BinaryOp, Expr)>) -> Expr {
+    rest.into_iter().fold(first, |lhs, (op, rhs)| {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    })
+}
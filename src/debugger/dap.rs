@@ -0,0 +1,767 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use dmg::cpu::{Reg8, Reg16};
+use super::{Debugger, Stop};
+use super::breakpoint::current_bank;
+use super::command::{self, Address};
+use super::json::Json;
+
+// largest message body accepted, a bigger Content-Length drops the client
+const MAX_MESSAGE_LEN: usize = 4 << 20;
+
+const REGISTERS: usize = 1;
+const FLAGS: usize = 2;
+
+enum Event {
+    Connected(Box<dyn Write + Send>),
+    Message(Json),
+    Disconnected,
+}
+
+// Debug Adapter Protocol over TCP or stdio, for editors. Like the GDB
+// server, requests are read on a background thread and handled from the
+// frontend's loop.
+pub struct DapServer {
+    events: Receiver<Event>,
+    client: Option<Box<dyn Write + Send>>,
+    seq: i64,
+    // launched rather than attached: disconnecting ends the emulator
+    launched: bool,
+    stop_on_entry: bool,
+    // debugger ids of the breakpoints set per source file and by name
+    source_breakpoints: Vec<(String, Vec<usize>)>,
+    function_breakpoints: Vec<usize>,
+    sources: Vec<Source>,
+}
+
+// An RGBDS source file, with the label each line's address is known by.
+// .sym files have no line numbers, so only labelled lines and the first
+// instruction after a label can take breakpoints.
+struct Source {
+    path: String,
+    lines: Vec<Option<String>>,
+}
+
+impl DapServer {
+    fn new(events: Receiver<Event>) -> DapServer {
+        DapServer {
+            events,
+            client: None,
+            seq: 1,
+            launched: false,
+            stop_on_entry: false,
+            source_breakpoints: Vec::new(),
+            function_breakpoints: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, mut fields: Vec<(&str, Json)>) {
+        fields.insert(0, ("seq", Json::from(self.seq)));
+        self.seq += 1;
+        let body = Json::object(fields).to_string();
+        let failed = match self.client {
+            Some(ref mut client) => {
+                let header = format!("Content-Length: {}\r\n\r\n", body.len());
+                client.write_all(header.as_bytes())
+                    .and_then(|_| client.write_all(body.as_bytes()))
+                    .and_then(|_| client.flush())
+                    .is_err()
+            },
+            None => false
+        };
+        if failed {
+            self.client = None;
+        }
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) {
+        let request_seq = request.get("seq").clone();
+        let command = request.get("command").clone();
+        match result {
+            Ok(body) => self.send(vec![
+                ("type", "response".into()),
+                ("request_seq", request_seq),
+                ("success", true.into()),
+                ("command", command),
+                ("body", body),
+            ]),
+            Err(message) => self.send(vec![
+                ("type", "response".into()),
+                ("request_seq", request_seq),
+                ("success", false.into()),
+                ("command", command),
+                ("message", message.into()),
+            ]),
+        }
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ]);
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) {
+        let mut body = vec![
+            ("reason", reason.into()),
+            ("threadId", Json::from(1usize)),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(description) = description {
+            body.push(("description", description.into()));
+        }
+        self.event("stopped", Json::object(body));
+    }
+
+    // the first line defining a label, for stack frames
+    fn label_line(&self, name: &str) -> Option<(&str, usize)> {
+        self.sources.iter()
+            .filter_map(|source| source.lines.iter()
+                        .position(|label| label.as_ref().map(|l| l.as_str()) == Some(name))
+                        .map(|line| (source.path.as_str(), line + 1)))
+            .next()
+    }
+}
+
+fn spawn_reader<R: BufRead + Send + 'static>(reader: R, events: Sender<Event>) {
+    thread::spawn(move || {
+        read_messages(reader, &events);
+        let _ = events.send(Event::Disconnected);
+    });
+}
+
+// "Content-Length: N" headers, a blank line, then N bytes of JSON
+fn read_messages<R: BufRead>(mut reader: R, events: &Sender<Event>) {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            let mut parts = header.splitn(2, ':');
+            if parts.next().map(|name| name.trim().eq_ignore_ascii_case("content-length"))
+                == Some(true) {
+                length = parts.next().and_then(|n| n.trim().parse::<usize>().ok());
+            }
+        }
+        let length = match length {
+            Some(length) if length > MAX_MESSAGE_LEN => {
+                eprintln!("DAP message of {} bytes is too large, disconnecting", length);
+                return;
+            },
+            Some(length) => length,
+            None => continue
+        };
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        match Json::parse(&String::from_utf8_lossy(&body)) {
+            Ok(message) => if events.send(Event::Message(message)).is_err() { return },
+            Err(e) => eprintln!("Invalid DAP message: {}", e),
+        }
+    }
+}
+
+// Keep the protocol's stdout to ourselves, sending everything else
+// printed (REPL output, serial) to stderr instead
+#[cfg(unix)]
+pub fn take_stdout() -> io::Result<fs::File> {
+    use std::os::unix::io::FromRawFd;
+    use libc;
+
+    io::stdout().flush()?;
+    unsafe {
+        let protocol = libc::dup(1);
+        if protocol < 0 || libc::dup2(2, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fs::File::from_raw_fd(protocol))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn take_stdout() -> io::Result<fs::File> {
+    Err(io::Error::new(io::ErrorKind::Other, "DAP over stdio needs a Unix host"))
+}
+
+impl Debugger {
+    pub fn listen_dap(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (reader, writer) = match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok(streams) => streams,
+                    Err(_) => continue
+                };
+                if sender.send(Event::Connected(Box::new(writer))).is_err() {
+                    break;
+                }
+                read_messages(io::BufReader::new(reader), &sender);
+                if sender.send(Event::Disconnected).is_err() {
+                    break;
+                }
+            }
+        });
+        self.dap = Some(DapServer::new(receiver));
+        println!("Waiting for a DAP client on port {}", port);
+        Ok(())
+    }
+
+    // stdin and the stdout from take_stdout carry the protocol, so the
+    // debugger must have been made without a REPL
+    pub fn serve_dap_stdio(&mut self, protocol: fs::File) {
+        let (sender, receiver) = channel();
+        sender.send(Event::Connected(Box::new(protocol))).unwrap();
+        spawn_reader(io::BufReader::new(io::stdin()), sender);
+        self.dap = Some(DapServer::new(receiver));
+    }
+
+    pub(super) fn poll_dap(&mut self) {
+        loop {
+            let event = match self.dap {
+                Some(ref dap) => match dap.events.try_recv() {
+                    Ok(event) => event,
+                    Err(_) => return
+                },
+                None => return
+            };
+            match event {
+                // hold still until the client is configured
+                Event::Connected(client) => {
+                    self.running = false;
+                    self.until = None;
+                    if let Some(ref mut dap) = self.dap {
+                        dap.client = Some(client);
+                    }
+                },
+                Event::Message(message) => {
+                    if message.get("type").as_str() == Some("request") {
+                        self.dap_message(&message);
+                    }
+                },
+                Event::Disconnected => self.detach_dap(),
+            }
+        }
+    }
+
+    pub(super) fn report_dap_stop(&mut self, reason: Stop) {
+        let (reason, description) = match reason {
+            Stop::Breakpoint => ("breakpoint", None),
+            Stop::Watchpoint(hit) =>
+                ("data breakpoint", Some(format!("Watchpoint {} at {:04X}", hit.id, hit.addr))),
+            Stop::Interrupt => ("pause", None),
+            Stop::Reached => ("step", None),
+            Stop::Fault(fault) => ("exception", Some(fault.to_string())),
+            Stop::Panic => ("exception", Some("Emulation fault, see the crash report".into())),
+        };
+        if let Some(ref mut dap) = self.dap {
+            dap.stopped(reason, description);
+        }
+    }
+
+    fn dap_message(&mut self, request: &Json) {
+        let command = request.get("command").as_str().unwrap_or("").to_string();
+        let result = self.dap_request(&command, request.get("arguments"));
+        if let Some(ref mut dap) = self.dap {
+            dap.respond(request, result);
+        }
+
+        // actions whose events should follow the response
+        match command.as_str() {
+            "initialize" => if let Some(ref mut dap) = self.dap {
+                dap.event("initialized", Json::Null);
+            },
+            "configurationDone" => {
+                let stop_on_entry = self.dap.as_ref().is_some_and(|dap| dap.stop_on_entry);
+                if stop_on_entry {
+                    if let Some(ref mut dap) = self.dap {
+                        dap.stopped("entry", None);
+                    }
+                } else {
+                    self.run_until(None);
+                }
+            },
+            "continue" => self.run_until(None),
+            "pause" if self.running => self.stop(Stop::Interrupt),
+            "next" | "stepIn" => {
+                if command == "next" {
+                    self.next();
+                } else {
+                    self.step(1);
+                }
+                // stepping over a call runs, and reports when it returns
+                if !self.running {
+                    self.report_dap_stop(Stop::Reached);
+                }
+            },
+            "stepOut" => self.finish(),
+            "disconnect" => {
+                let terminate = request.get("arguments").get("terminateDebuggee").as_bool();
+                let launched = self.dap.as_ref().is_some_and(|dap| dap.launched);
+                self.detach_dap();
+                if terminate.unwrap_or(launched) {
+                    self.quit = true;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn dap_request(&mut self, command: &str, args: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => Ok(Json::object(vec![
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsFunctionBreakpoints", true.into()),
+                ("supportsConditionalBreakpoints", true.into()),
+                ("supportsSetVariable", true.into()),
+                ("supportsReadMemoryRequest", true.into()),
+                ("supportsWriteMemoryRequest", true.into()),
+                ("supportsTerminateRequest", false.into()),
+            ])),
+            // the ROM comes from the command line; both just configure
+            "launch" | "attach" => {
+                if let Some(ref mut dap) = self.dap {
+                    dap.launched = command == "launch";
+                    dap.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+                }
+                Ok(Json::Null)
+            },
+            "configurationDone" | "pause" | "next" | "stepIn" | "stepOut" | "disconnect" =>
+                Ok(Json::Null),
+            "continue" => Ok(Json::object(vec![("allThreadsContinued", true.into())])),
+            "setBreakpoints" => self.dap_set_breakpoints(args),
+            "setFunctionBreakpoints" => self.dap_set_function_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Json::object(vec![("breakpoints", Json::Array(vec![]))])),
+            "threads" => Ok(Json::object(vec![
+                ("threads", Json::Array(vec![Json::object(vec![
+                    ("id", Json::from(1usize)),
+                    ("name", "SM83".into()),
+                ])])),
+            ])),
+            "stackTrace" => Ok(self.dap_stack_trace(args)),
+            "scopes" => Ok(Json::object(vec![
+                ("scopes", Json::Array(vec![
+                    scope("Registers", REGISTERS),
+                    scope("Flags", FLAGS),
+                ])),
+            ])),
+            "variables" => Ok(self.dap_variables(args)),
+            "setVariable" => self.dap_set_variable(args),
+            "evaluate" => {
+                let expression = args.get("expression").as_str().unwrap_or("");
+                let expr = command::parse_expr(expression)?;
+                let value = expr.eval(&self.dmg, self.symbols.as_ref())?;
+                Ok(Json::object(vec![
+                    ("result", format!("{} (${:X})", value, value).into()),
+                    ("variablesReference", Json::from(0usize)),
+                ]))
+            },
+            "readMemory" => {
+                let addr = memory_address(args)?;
+                let count = args.get("count").as_i64().unwrap_or(0).max(0) as usize;
+                let count = count.min(0x10000 - addr);
+                let interconnect = self.dmg.interconnect();
+                let data: Vec<u8> = (0..count)
+                    .map(|i| interconnect.peek((addr + i) as u16))
+                    .collect();
+                Ok(Json::object(vec![
+                    ("address", format!("0x{:04X}", addr).into()),
+                    ("data", base64_encode(&data).into()),
+                    ("unreadableBytes", Json::from(0usize)),
+                ]))
+            },
+            "writeMemory" => {
+                let addr = memory_address(args)?;
+                let data = base64_decode(args.get("data").as_str().unwrap_or(""))
+                    .ok_or("invalid base64 data")?;
+                let count = data.len().min(0x10000 - addr);
+                for (i, &byte) in data[..count].iter().enumerate() {
+                    self.dmg.poke((addr + i) as u16, byte);
+                }
+                Ok(Json::object(vec![("bytesWritten", Json::from(count))]))
+            },
+            _ => Err(format!("unsupported request: {}", command))
+        }
+    }
+
+    fn dap_set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let path = args.get("source").get("path").as_str()
+            .ok_or("source has no path")?
+            .to_string();
+
+        // replaces every breakpoint previously set in this file
+        let old = match self.dap {
+            Some(ref mut dap) => {
+                let index = dap.source_breakpoints.iter().position(|(p, _)| *p == path);
+                index.map_or(Vec::new(), |i| dap.source_breakpoints.remove(i).1)
+            },
+            None => Vec::new()
+        };
+        self.breakpoints.retain(|b| !old.contains(&b.id));
+
+        let source = load_source(&path);
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        let requested = args.get("breakpoints").as_array().unwrap_or(&[]);
+        for breakpoint in requested {
+            let line = breakpoint.get("line").as_i64().unwrap_or(0);
+            let label = source.as_ref().ok()
+                .and_then(|source| source.lines.get((line - 1).max(0) as usize))
+                .and_then(|label| label.clone());
+            let location = label.as_ref().and_then(|label| {
+                self.symbols.as_ref().and_then(|symbols| symbols.lookup(label))
+            });
+            let condition = match breakpoint.get("condition").as_str() {
+                Some(condition) if !condition.trim().is_empty() =>
+                    command::parse_expr(condition).map(Some),
+                _ => Ok(None)
+            };
+            let result = match (location, condition) {
+                (Some(location), Ok(condition)) => {
+                    let id = self.add_breakpoint(location, condition);
+                    ids.push(id);
+                    Json::object(vec![
+                        ("id", Json::from(id)),
+                        ("verified", true.into()),
+                        ("line", Json::from(line)),
+                    ])
+                },
+                (_, Err(e)) => unverified(line, e),
+                (None, _) => {
+                    let message = match (&source, &label, &self.symbols) {
+                        (Err(e), _, _) => e.clone(),
+                        (_, None, _) => "No label at this line".into(),
+                        (_, _, None) => "No symbol file loaded".into(),
+                        (_, Some(label), _) => format!("{} isn't in the symbol file", label),
+                    };
+                    unverified(line, message)
+                }
+            };
+            results.push(result);
+        }
+
+        if let Some(ref mut dap) = self.dap {
+            dap.source_breakpoints.push((path.clone(), ids));
+            if let Ok(source) = source {
+                dap.sources.retain(|s| s.path != path);
+                dap.sources.push(source);
+            }
+        }
+        Ok(Json::object(vec![("breakpoints", Json::Array(results))]))
+    }
+
+    // by label or address, like the REPL's break
+    fn dap_set_function_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let old = match self.dap {
+            Some(ref mut dap) => dap.function_breakpoints.split_off(0),
+            None => Vec::new()
+        };
+        self.breakpoints.retain(|b| !old.contains(&b.id));
+
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        let requested = args.get("breakpoints").as_array().unwrap_or(&[]);
+        for breakpoint in requested {
+            let name = breakpoint.get("name").as_str().unwrap_or("");
+            let condition = match breakpoint.get("condition").as_str() {
+                Some(condition) if !condition.trim().is_empty() =>
+                    command::parse_expr(condition).map(Some),
+                _ => Ok(None)
+            };
+            let result = match (self.resolve(&Address(name.into())), condition) {
+                (Ok(location), Ok(condition)) => {
+                    let id = self.add_breakpoint(location, condition);
+                    ids.push(id);
+                    Json::object(vec![("id", Json::from(id)), ("verified", true.into())])
+                },
+                (Err(e), _) | (_, Err(e)) => Json::object(vec![
+                    ("verified", false.into()),
+                    ("message", e.into()),
+                ]),
+            };
+            results.push(result);
+        }
+
+        if let Some(ref mut dap) = self.dap {
+            dap.function_breakpoints = ids;
+        }
+        Ok(Json::object(vec![("breakpoints", Json::Array(results))]))
+    }
+
+    // innermost first, as in bt
+    fn dap_stack_trace(&self, args: &Json) -> Json {
+        let pc = self.dmg.cpu().current_pc();
+        let mut locations = vec![(current_bank(&self.dmg, pc), pc)];
+        for frame in self.dmg.cpu().call_stack().iter().rev() {
+            locations.push((frame.caller_bank, frame.caller));
+        }
+
+        let start = args.get("startFrame").as_i64().unwrap_or(0).max(0) as usize;
+        let levels = match args.get("levels").as_i64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => locations.len()
+        };
+        let frames: Vec<Json> = locations.iter().enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, &(bank, addr))| {
+                let name = self.symbol(bank, addr)
+                    .unwrap_or_else(|| format!("{:02X}:{:04X}", bank, addr));
+                let mut frame = vec![
+                    ("id", Json::from(id)),
+                    ("name", name.clone().into()),
+                    ("instructionPointerReference", format!("0x{:04X}", addr).into()),
+                    ("line", Json::from(0usize)),
+                    ("column", Json::from(0usize)),
+                ];
+                let line = self.dap.as_ref().and_then(|dap| dap.label_line(&name));
+                if let Some((path, line)) = line {
+                    frame[3] = ("line", Json::from(line));
+                    frame[4] = ("column", Json::from(1usize));
+                    frame.push(("source", Json::object(vec![("path", path.into())])));
+                }
+                Json::object(frame)
+            })
+            .collect();
+        Json::object(vec![
+            ("stackFrames", Json::Array(frames)),
+            ("totalFrames", Json::from(locations.len())),
+        ])
+    }
+
+    fn dap_variables(&self, args: &Json) -> Json {
+        let cpu = self.dmg.cpu();
+        let variables = match args.get("variablesReference").as_i64() {
+            Some(r) if r == REGISTERS as i64 => {
+                let pairs = [("af", Reg16::AF), ("bc", Reg16::BC), ("de", Reg16::DE),
+                             ("hl", Reg16::HL), ("sp", Reg16::SP), ("pc", Reg16::PC)];
+                let regs = [("a", Reg8::A), ("f", Reg8::F), ("b", Reg8::B), ("c", Reg8::C),
+                            ("d", Reg8::D), ("e", Reg8::E), ("h", Reg8::H), ("l", Reg8::L)];
+                let mut variables: Vec<Json> = pairs.iter().map(|&(name, reg)| {
+                    let value = cpu.read_reg16(reg);
+                    Json::object(vec![
+                        ("name", name.into()),
+                        ("value", format!("${:04X}", value).into()),
+                        ("variablesReference", Json::from(0usize)),
+                        ("memoryReference", format!("0x{:04X}", value).into()),
+                    ])
+                }).collect();
+                variables.extend(regs.iter().map(|&(name, reg)| {
+                    variable(name, format!("${:02X}", cpu.read_reg(reg)))
+                }));
+                variables
+            },
+            Some(r) if r == FLAGS as i64 => {
+                let f = cpu.read_reg(Reg8::F);
+                ["z", "n", "h", "c"].iter().enumerate()
+                    .map(|(i, &name)| variable(name, format!("{}", f >> (7 - i) & 1)))
+                    .collect()
+            },
+            _ => Vec::new()
+        };
+        Json::object(vec![("variables", Json::Array(variables))])
+    }
+
+    fn dap_set_variable(&mut self, args: &Json) -> Result<Json, String> {
+        let name = args.get("name").as_str().unwrap_or("");
+        let expr = command::parse_expr(args.get("value").as_str().unwrap_or(""))?;
+        let value = expr.eval(&self.dmg, self.symbols.as_ref())?;
+        let shown = match args.get("variablesReference").as_i64() {
+            Some(r) if r == REGISTERS as i64 => {
+                let reg = match command::parse_register(name) {
+                    Some(reg) => reg,
+                    None => return Err(format!("unknown register: {}", name))
+                };
+                self.set_register(reg, value as u16);
+                match reg {
+                    command::Register::R8(reg) => format!("${:02X}", self.dmg.cpu().read_reg(reg)),
+                    command::Register::R16(reg) =>
+                        format!("${:04X}", self.dmg.cpu().read_reg16(reg)),
+                }
+            },
+            Some(r) if r == FLAGS as i64 => {
+                let bit = match name {
+                    "z" => 7,
+                    "n" => 6,
+                    "h" => 5,
+                    "c" => 4,
+                    _ => return Err(format!("unknown flag: {}", name))
+                };
+                let f = self.dmg.cpu().read_reg(Reg8::F) & !(1 << bit);
+                let f = f | if value != 0 { 1 << bit } else { 0 };
+                self.dmg.cpu_mut().write_reg(Reg8::F, f);
+                format!("{}", (value != 0) as u8)
+            },
+            _ => return Err("unknown variable".into())
+        };
+        Ok(Json::object(vec![("value", shown.into())]))
+    }
+
+    // drop what the client set; it may have left without clearing it
+    fn detach_dap(&mut self) {
+        let ids: Vec<usize> = match self.dap {
+            Some(ref mut dap) => {
+                dap.client = None;
+                let mut ids = dap.function_breakpoints.split_off(0);
+                for (_, source_ids) in dap.source_breakpoints.drain(..) {
+                    ids.extend(source_ids);
+                }
+                ids
+            },
+            None => return
+        };
+        self.breakpoints.retain(|b| !ids.contains(&b.id));
+        self.run_until(None);
+    }
+}
+
+fn scope(name: &str, reference: usize) -> Json {
+    Json::object(vec![
+        ("name", name.into()),
+        ("variablesReference", Json::from(reference)),
+        ("expensive", false.into()),
+    ])
+}
+
+fn variable(name: &str, value: String) -> Json {
+    Json::object(vec![
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", Json::from(0usize)),
+    ])
+}
+
+fn unverified(line: i64, message: String) -> Json {
+    Json::object(vec![
+        ("verified", false.into()),
+        ("line", Json::from(line)),
+        ("message", message.into()),
+    ])
+}
+
+// memoryReference plus offset; references are "0x"/"$" hex or decimal
+fn memory_address(args: &Json) -> Result<usize, String> {
+    let reference = args.get("memoryReference").as_str().unwrap_or("");
+    let base = if let Some(hex) = reference.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = reference.strip_prefix('$') {
+        usize::from_str_radix(hex, 16).ok()
+    } else {
+        reference.parse().ok()
+    };
+    let offset = args.get("offset").as_i64().unwrap_or(0);
+    match base.map(|base| base as i64 + offset) {
+        Some(addr) if (0..=0xFFFF).contains(&addr) => Ok(addr as usize),
+        _ => Err(format!("invalid memory reference: {}", reference))
+    }
+}
+
+fn load_source(path: &str) -> Result<Source, String> {
+    let mut text = String::new();
+    fs::File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("Unable to read {}: {}", path, e))?;
+
+    let mut lines = Vec::new();
+    let mut scope = String::new();
+    // a label waiting for the instruction it names
+    let mut pending: Option<String> = None;
+    for line in text.lines() {
+        let code = line.split(';').next().unwrap();
+        let (label, rest) = match line_label(code) {
+            Some((label, rest)) => {
+                let label = if label.starts_with('.') {
+                    format!("{}{}", scope, label)
+                } else {
+                    if let Some(dot) = label.find('.') {
+                        scope = label[..dot].to_string();
+                    } else {
+                        scope = label.to_string();
+                    }
+                    label.to_string()
+                };
+                (Some(label), rest)
+            },
+            None => (None, code)
+        };
+        if label.is_some() {
+            pending = label;
+        }
+        lines.push(pending.clone());
+        if !rest.trim().is_empty() {
+            pending = None;
+        }
+    }
+    Ok(Source { path: path.into(), lines })
+}
+
+// "Label:", "Label::", ".local:" or ".local" at the start of a line,
+// and whatever follows it
+fn line_label(code: &str) -> Option<(&str, &str)> {
+    let end = code.find(|c: char| !(c.is_alphanumeric() || "_.@#$".contains(c)))
+        .unwrap_or(code.len());
+    if end == 0 {
+        return None;
+    }
+    let (label, rest) = code.split_at(end);
+    if let Some(rest) = rest.strip_prefix("::") {
+        Some((label, rest))
+    } else if let Some(rest) = rest.strip_prefix(':') {
+        Some((label, rest))
+    } else if label.starts_with('.') && label.len() > 1 {
+        Some((label, rest))
+    } else {
+        None
+    }
+}
+
+const BASE64: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let digits: Option<Vec<u32>> = s.trim_end_matches('=').bytes()
+        .map(|c| BASE64.iter().position(|&b| b == c).map(|i| i as u32))
+        .collect();
+    let digits = digits?;
+    let mut data = Vec::new();
+    for chunk in digits.chunks(4) {
+        let n = chunk.iter().enumerate()
+            .fold(0u32, |n, (i, &d)| n | d << (18 - 6 * i));
+        for i in 0..chunk.len().saturating_sub(1) {
+            data.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(data)
+}
@@ -0,0 +1,43 @@
+use dmg::cpu::{Instruction, Opcode};
+
+const BANK_SIZE: usize = 0x4000;
+
+// "01:4000  21 00 C0  ld hl, $C000"
+pub fn format_line(bank: usize, instr: &Instruction, bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:02X}:{:04X}  {:<9} {}", bank, instr.pc(), hex.join(" "), instr)
+}
+
+// undefined opcodes decode as 0 bytes long
+pub fn length(instr: &Instruction) -> usize {
+    (instr.bytes() as usize).max(1)
+}
+
+// where a jump, call or RST goes, if known without running it
+pub fn branch_target(instr: &Instruction) -> Option<u16> {
+    match instr.opcode() {
+        Opcode::Jmp(_, nn) | Opcode::Call(_, nn) => Some(nn),
+        Opcode::Jr(_, e) => Some(instr.pc().wrapping_add(2).wrapping_add(e as u16)),
+        Opcode::Rst(n) => Some(n as u16),
+        _ => None
+    }
+}
+
+// linear sweep over one bank of a ROM image, without running anything
+pub fn print_bank(rom: &[u8], bank: usize) -> Result<(), String> {
+    let start = bank * BANK_SIZE;
+    if start >= rom.len() {
+        return Err(format!("ROM has no bank {:02X}", bank));
+    }
+    let data = &rom[start..(start + BANK_SIZE).min(rom.len())];
+    let base = if bank == 0 { 0 } else { BANK_SIZE };
+    let mut offset = 0;
+    while offset < data.len() {
+        let pc = (base + offset) as u16;
+        let instr = Instruction::from_bytes(pc, &data[offset..]);
+        let end = (offset + length(&instr)).min(data.len());
+        println!("{}", format_line(bank, &instr, &data[offset..end]));
+        offset += length(&instr);
+    }
+    Ok(())
+}
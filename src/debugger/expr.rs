@@ -0,0 +1,211 @@
+use std::fmt;
+
+use dmg::Dmg;
+use dmg::cpu::Reg8;
+use super::command::Register;
+use super::symbols::Symbols;
+
+// Debugger expressions: `a == 3 && [wPlayerX] > $10`. Values are wide
+// enough that 16-bit arithmetic doesn't wrap, comparisons yield 0 or 1.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Flag(Flag),
+    // a label, resolved to its address when evaluated
+    Symbol(String),
+    // byte at an address
+    Deref(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Flag {
+    Z,
+    N,
+    H,
+    C,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Expr {
+    pub fn eval(&self, dmg: &Dmg, symbols: Option<&Symbols>) -> Result<i64, String> {
+        match *self {
+            Expr::Number(n) => Ok(n),
+            Expr::Register(Register::R8(reg)) => Ok(dmg.cpu().read_reg(reg) as i64),
+            Expr::Register(Register::R16(reg)) => Ok(dmg.cpu().read_reg16(reg) as i64),
+            Expr::Flag(flag) => {
+                let bit = match flag {
+                    Flag::Z => 7,
+                    Flag::N => 6,
+                    Flag::H => 5,
+                    Flag::C => 4,
+                };
+                Ok((dmg.cpu().read_reg(Reg8::F) >> bit & 1) as i64)
+            },
+            Expr::Symbol(ref name) => symbols
+                .and_then(|symbols| symbols.lookup(name))
+                .map(|location| location.addr as i64)
+                .ok_or_else(|| format!("Unknown symbol: {}", name)),
+            Expr::Deref(ref addr) => {
+                let addr = addr.eval(dmg, symbols)?;
+                Ok(dmg.interconnect().peek(addr as u16) as i64)
+            },
+            Expr::Unary(op, ref operand) => {
+                let value = operand.eval(dmg, symbols)?;
+                Ok(match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Complement => !value,
+                })
+            },
+            // && and || short-circuit so a guard can protect a lookup
+            Expr::Binary(BinaryOp::And, ref lhs, ref rhs) =>
+                Ok((lhs.eval(dmg, symbols)? != 0 && rhs.eval(dmg, symbols)? != 0) as i64),
+            Expr::Binary(BinaryOp::Or, ref lhs, ref rhs) =>
+                Ok((lhs.eval(dmg, symbols)? != 0 || rhs.eval(dmg, symbols)? != 0) as i64),
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let lhs = lhs.eval(dmg, symbols)?;
+                let rhs = rhs.eval(dmg, symbols)?;
+                match op {
+                    BinaryOp::Eq => Ok((lhs == rhs) as i64),
+                    BinaryOp::Ne => Ok((lhs != rhs) as i64),
+                    BinaryOp::Lt => Ok((lhs < rhs) as i64),
+                    BinaryOp::Le => Ok((lhs <= rhs) as i64),
+                    BinaryOp::Gt => Ok((lhs > rhs) as i64),
+                    BinaryOp::Ge => Ok((lhs >= rhs) as i64),
+                    BinaryOp::BitOr => Ok(lhs | rhs),
+                    BinaryOp::BitXor => Ok(lhs ^ rhs),
+                    BinaryOp::BitAnd => Ok(lhs & rhs),
+                    BinaryOp::Add => Ok(lhs.wrapping_add(rhs)),
+                    BinaryOp::Sub => Ok(lhs.wrapping_sub(rhs)),
+                    BinaryOp::Mul => Ok(lhs.wrapping_mul(rhs)),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 =>
+                        Err("Division by zero".into()),
+                    BinaryOp::Div => Ok(lhs.wrapping_div(rhs)),
+                    BinaryOp::Rem => Ok(lhs.wrapping_rem(rhs)),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match *self {
+            Expr::Binary(op, _, _) => op.precedence(),
+            _ => 8
+        }
+    }
+}
+
+impl BinaryOp {
+    // higher binds tighter, matching the parser's levels
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt |
+            BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 3,
+            BinaryOp::BitOr | BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Add | BinaryOp::Sub => 6,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 7,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+        }
+    }
+}
+
+// only parenthesizes where the parse would otherwise differ
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Register(Register::R8(reg)) => write!(f, "{}", reg),
+            Expr::Register(Register::R16(reg)) => write!(f, "{}", reg),
+            Expr::Flag(flag) => {
+                let name = match flag {
+                    Flag::Z => "zf",
+                    Flag::N => "nf",
+                    Flag::H => "hf",
+                    Flag::C => "cf",
+                };
+                write!(f, "{}", name)
+            },
+            Expr::Symbol(ref name) => write!(f, "{}", name),
+            Expr::Deref(ref addr) => write!(f, "[{}]", addr),
+            Expr::Unary(op, ref operand) => {
+                let symbol = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                    UnaryOp::Complement => "~",
+                };
+                if operand.precedence() < 8 {
+                    write!(f, "{}({})", symbol, operand)
+                } else {
+                    write!(f, "{}{}", symbol, operand)
+                }
+            },
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                if lhs.precedence() < op.precedence() {
+                    write!(f, "({})", lhs)?;
+                } else {
+                    write!(f, "{}", lhs)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                // operators are left-associative
+                if rhs.precedence() <= op.precedence() {
+                    write!(f, "({})", rhs)
+                } else {
+                    write!(f, "{}", rhs)
+                }
+            }
+        }
+    }
+}
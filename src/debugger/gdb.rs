@@ -0,0 +1,477 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use dmg::cpu::Reg16;
+use dmg::fault::Fault;
+use dmg::watch::Access;
+use super::{Debugger, Stop};
+use super::breakpoint::Location;
+
+// Registers as GDB sees them, each 16 bits little-endian
+const REGISTERS: [Reg16; 6] = [Reg16::AF, Reg16::BC, Reg16::DE, Reg16::HL,
+                               Reg16::SP, Reg16::PC];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rustboy.sm83.core">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="int"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+enum Event {
+    Connected(TcpStream),
+    Packet(String),
+    // ^C from the client
+    Interrupt,
+    Disconnected,
+}
+
+// GDB remote serial protocol over TCP. One client at a time is read on a
+// background thread, and its packets are handled from the frontend's loop
+// like REPL input, so both share breakpoints and watchpoints.
+pub struct GdbServer {
+    events: Receiver<Event>,
+    client: Option<TcpStream>,
+    // a continue is in flight, answer it when the emulator stops
+    waiting: bool,
+    // debugger ids of the breakpoints and watchpoints the client set
+    breakpoints: Vec<(u16, usize)>,
+    watchpoints: Vec<(Access, u16, u16, usize)>,
+}
+
+impl GdbServer {
+    fn listen(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                match stream.try_clone() {
+                    Ok(writer) => if sender.send(Event::Connected(writer)).is_err() {
+                        break
+                    },
+                    Err(_) => continue
+                }
+                read_packets(stream, &sender);
+                if sender.send(Event::Disconnected).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(GdbServer {
+            events: receiver,
+            client: None,
+            waiting: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+        let failed = match self.client {
+            Some(ref mut client) => client.write_all(packet.as_bytes()).is_err(),
+            None => false
+        };
+        if failed {
+            self.client = None;
+        }
+    }
+}
+
+// "$data#cs" packets, acknowledged as they arrive
+fn read_packets(mut stream: TcpStream, events: &Sender<Event>) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(_) => return
+    };
+    let mut bytes = io::BufReader::new(reader).bytes();
+    while let Some(Ok(byte)) = bytes.next() {
+        let event = match byte {
+            0x03 => Event::Interrupt,
+            b'$' => {
+                let mut data = Vec::new();
+                loop {
+                    match bytes.next() {
+                        Some(Ok(b'#')) => break,
+                        Some(Ok(b)) => data.push(b),
+                        _ => return
+                    }
+                }
+                let checksum = match (bytes.next(), bytes.next()) {
+                    (Some(Ok(hi)), Some(Ok(lo))) => {
+                        let digits = [hi, lo];
+                        String::from_utf8_lossy(&digits).into_owned()
+                    },
+                    _ => return
+                };
+                let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                if u8::from_str_radix(&checksum, 16) != Ok(sum) {
+                    if stream.write_all(b"-").is_err() { return }
+                    continue;
+                }
+                if stream.write_all(b"+").is_err() { return }
+                Event::Packet(String::from_utf8_lossy(&data).into_owned())
+            },
+            // acks for our own packets
+            _ => continue
+        };
+        if events.send(event).is_err() { return }
+    }
+}
+
+impl Debugger {
+    pub fn listen_gdb(&mut self, port: u16) -> io::Result<()> {
+        self.gdb = Some(GdbServer::listen(port)?);
+        println!("Waiting for GDB on port {}", port);
+        Ok(())
+    }
+
+    pub(super) fn poll_gdb(&mut self) {
+        loop {
+            let event = match self.gdb {
+                Some(ref gdb) => match gdb.events.try_recv() {
+                    Ok(event) => event,
+                    Err(_) => return
+                },
+                None => return
+            };
+            match event {
+                // GDB expects the target to be halted when it attaches
+                Event::Connected(client) => {
+                    if let Ok(addr) = client.peer_addr() {
+                        println!("GDB connected from {}", addr);
+                    }
+                    self.running = false;
+                    self.until = None;
+                    if let Some(ref mut gdb) = self.gdb {
+                        gdb.client = Some(client);
+                        gdb.waiting = false;
+                    }
+                },
+                Event::Packet(packet) => {
+                    if let Some(reply) = self.gdb_packet(&packet) {
+                        if let Some(ref mut gdb) = self.gdb {
+                            gdb.send(&reply);
+                        }
+                    }
+                },
+                Event::Interrupt => if self.running {
+                    self.stop(Stop::Interrupt);
+                },
+                Event::Disconnected => {
+                    println!("GDB disconnected");
+                    self.detach_gdb();
+                },
+            }
+        }
+    }
+
+    // answer a continue once the emulator stops
+    pub(super) fn report_gdb_stop(&mut self, reason: Stop) {
+        let reply = self.stop_reply(reason);
+        if let Some(ref mut gdb) = self.gdb {
+            if gdb.waiting {
+                gdb.waiting = false;
+                gdb.send(&reply);
+            }
+        }
+    }
+
+    fn stop_reply(&self, reason: Stop) -> String {
+        match reason {
+            Stop::Breakpoint => "T05swbreak:;".into(),
+            Stop::Watchpoint(hit) => {
+                let access = self.dmg.watchpoints().iter()
+                    .find(|w| w.id == hit.id)
+                    .map(|w| w.access)
+                    .unwrap_or(hit.access);
+                let kind = match access {
+                    Access::Write => "watch",
+                    Access::Read => "rwatch",
+                    Access::ReadWrite => "awatch",
+                };
+                format!("T05{}:{:x};", kind, hit.addr)
+            },
+            Stop::Interrupt => "T02".into(),
+            Stop::Reached => "T05".into(),
+            // SIGILL for a lock up, the rest stop like a breakpoint
+            Stop::Fault(Fault::IllegalOpcode(_)) => "T04".into(),
+            Stop::Fault(_) => "T05".into(),
+            Stop::Panic => "T06".into(),
+        }
+    }
+
+    // drop what the client set and let the game run on
+    fn detach_gdb(&mut self) {
+        let (breakpoints, watchpoints) = match self.gdb {
+            Some(ref mut gdb) => {
+                gdb.client = None;
+                gdb.waiting = false;
+                (gdb.breakpoints.split_off(0), gdb.watchpoints.split_off(0))
+            },
+            None => return
+        };
+        for (_, id) in breakpoints {
+            self.breakpoints.retain(|b| b.id != id);
+        }
+        for (_, _, _, id) in watchpoints {
+            self.dmg.remove_watchpoint(id);
+        }
+        self.run_until(None);
+    }
+
+    // the reply, or None when it's sent later (continue) or not at all
+    fn gdb_packet(&mut self, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        match command {
+            "?" => Some("S05".into()),
+            "g" => {
+                let cpu = self.dmg.cpu();
+                Some(REGISTERS.iter().map(|&reg| hex_word(cpu.read_reg16(reg))).collect())
+            },
+            "G" => {
+                let values: Option<Vec<u16>> = (0..REGISTERS.len())
+                    .map(|i| args.get(i * 4..i * 4 + 4).and_then(parse_word))
+                    .collect();
+                match values {
+                    Some(values) => {
+                        for (&reg, value) in REGISTERS.iter().zip(values) {
+                            self.dmg.cpu_mut().write_reg16(reg, value);
+                        }
+                        Some("OK".into())
+                    },
+                    None => Some("E01".into())
+                }
+            },
+            "p" => match parse_hex(args).and_then(|n| REGISTERS.get(n)) {
+                Some(&reg) => Some(hex_word(self.dmg.cpu().read_reg16(reg))),
+                None => Some("E01".into())
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let reg = parts.next().and_then(parse_hex).and_then(|n| REGISTERS.get(n));
+                let value = parts.next().and_then(parse_word);
+                match (reg, value) {
+                    (Some(&reg), Some(value)) => {
+                        self.dmg.cpu_mut().write_reg16(reg, value);
+                        Some("OK".into())
+                    },
+                    _ => Some("E01".into())
+                }
+            },
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    let interconnect = self.dmg.interconnect();
+                    Some((0..len)
+                         .map(|i| format!("{:02x}", interconnect.peek(addr.wrapping_add(i))))
+                         .collect())
+                },
+                None => Some("E01".into())
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let data = parts.next().and_then(parse_bytes);
+                match (range, data) {
+                    (Some((addr, len)), Some(ref data)) if data.len() == len as usize => {
+                        for (i, &byte) in data.iter().enumerate() {
+                            self.dmg.poke(addr.wrapping_add(i as u16), byte);
+                        }
+                        Some("OK".into())
+                    },
+                    _ => Some("E01".into())
+                }
+            },
+            "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.dmg.cpu_mut().write_reg16(Reg16::PC, addr as u16);
+                }
+                if let Some(ref mut gdb) = self.gdb {
+                    gdb.waiting = true;
+                }
+                self.run_until(None);
+                None
+            },
+            "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.dmg.cpu_mut().write_reg16(Reg16::PC, addr as u16);
+                }
+                let stop = self.step_instruction().1.unwrap_or(Stop::Reached);
+                Some(self.stop_reply(stop))
+            },
+            "Z" => self.insert_gdb_point(args),
+            "z" => self.remove_gdb_point(args),
+            "D" => {
+                if let Some(ref mut gdb) = self.gdb {
+                    gdb.send("OK");
+                }
+                self.detach_gdb();
+                None
+            },
+            "k" => {
+                self.detach_gdb();
+                None
+            },
+            "H" => Some("OK".into()),
+            "q" => Some(self.gdb_query(packet)),
+            // unsupported, GDB falls back or gives up
+            _ => Some(String::new())
+        }
+    }
+
+    fn gdb_query(&self, query: &str) -> String {
+        if query.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+".into();
+        }
+        if let Some(range) = query.strip_prefix("qXfer:features:read:target.xml:") {
+            let mut parts = range.splitn(2, ',');
+            let offset = parts.next().and_then(parse_hex);
+            let len = parts.next().and_then(parse_hex);
+            return match (offset, len) {
+                (Some(offset), Some(len)) if offset < TARGET_XML.len() => {
+                    let end = (offset + len).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                    format!("{}{}", prefix, &TARGET_XML[offset..end])
+                },
+                (Some(_), Some(_)) => "l".into(),
+                _ => "E01".into()
+            };
+        }
+        match query {
+            "qAttached" => "1".into(),
+            "qC" => "QC1".into(),
+            "qfThreadInfo" => "m1".into(),
+            "qsThreadInfo" => "l".into(),
+            _ => String::new()
+        }
+    }
+
+    // Z0/Z1 breakpoints, Z2 write, Z3 read and Z4 access watchpoints
+    fn insert_gdb_point(&mut self, args: &str) -> Option<String> {
+        let (kind, addr, len) = match parse_point(args) {
+            Some(point) => point,
+            None => return Some("E01".into())
+        };
+        let end = addr.wrapping_add(len.max(1) - 1);
+        match kind {
+            0 | 1 => {
+                let location = Location { bank: None, addr };
+                let id = self.add_breakpoint(location, None);
+                if let Some(ref mut gdb) = self.gdb {
+                    gdb.breakpoints.push((addr, id));
+                }
+            },
+            2..=4 => {
+                let access = watch_access(kind);
+                let id = self.add_watchpoint(access, addr, end, None);
+                if let Some(ref mut gdb) = self.gdb {
+                    gdb.watchpoints.push((access, addr, end, id));
+                }
+            },
+            _ => return Some(String::new())
+        }
+        Some("OK".into())
+    }
+
+    fn remove_gdb_point(&mut self, args: &str) -> Option<String> {
+        let (kind, addr, len) = match parse_point(args) {
+            Some(point) => point,
+            None => return Some("E01".into())
+        };
+        let end = addr.wrapping_add(len.max(1) - 1);
+        let gdb = match self.gdb {
+            Some(ref mut gdb) => gdb,
+            None => return None
+        };
+        match kind {
+            0 | 1 => {
+                if let Some(index) = gdb.breakpoints.iter().position(|&(a, _)| a == addr) {
+                    let (_, id) = gdb.breakpoints.remove(index);
+                    self.breakpoints.retain(|b| b.id != id);
+                }
+            },
+            2..=4 => {
+                let access = watch_access(kind);
+                let index = gdb.watchpoints.iter()
+                    .position(|&(a, start, e, _)| a == access && start == addr && e == end);
+                if let Some(index) = index {
+                    let (_, _, _, id) = gdb.watchpoints.remove(index);
+                    self.dmg.remove_watchpoint(id);
+                }
+            },
+            _ => return Some(String::new())
+        }
+        Some("OK".into())
+    }
+}
+
+fn watch_access(kind: usize) -> Access {
+    match kind {
+        2 => Access::Write,
+        3 => Access::Read,
+        _ => Access::ReadWrite,
+    }
+}
+
+fn hex_word(value: u16) -> String {
+    format!("{:02x}{:02x}", value & 0xFF, value >> 8)
+}
+
+// little-endian, as in hex_word
+fn parse_word(s: &str) -> Option<u16> {
+    parse_bytes(s).and_then(|bytes| match bytes.len() {
+        2 => Some(bytes[0] as u16 | (bytes[1] as u16) << 8),
+        _ => None
+    })
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len() / 2)
+        .map(|i| s.get(i * 2..i * 2 + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+// "addr,length"
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let mut parts = s.splitn(2, ',');
+    let addr = parts.next().and_then(parse_hex).filter(|&a| a <= 0xFFFF);
+    let len = parts.next().and_then(parse_hex).filter(|&l| l <= 0x10000);
+    match (addr, len) {
+        (Some(addr), Some(len)) => Some((addr as u16, len.min(0xFFFF) as u16)),
+        _ => None
+    }
+}
+
+// "type,addr,kind", where kind is the length for watchpoints
+fn parse_point(s: &str) -> Option<(usize, u16, u16)> {
+    let mut parts = s.splitn(2, ',');
+    let kind = parts.next().and_then(parse_hex);
+    let range = parts.next().and_then(|rest| parse_range(rest.split(';').next().unwrap()));
+    match (kind, range) {
+        (Some(kind), Some((addr, len))) => Some((kind, addr, len)),
+        _ => None
+    }
+}
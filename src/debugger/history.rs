@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+
+use dmg::Dmg;
+use dmg::cpu::{Instruction, Reg16};
+use super::breakpoint::current_bank;
+use super::disasm;
+
+// instructions kept for `history` and crash reports
+pub const HISTORY_SIZE: usize = 256;
+
+// An executed instruction and the registers it started with
+pub struct Entry {
+    bank: usize,
+    pc: u16,
+    // decoded when shown, most instructions are never looked at
+    bytes: [u8; 3],
+    // AF BC DE HL SP
+    regs: [u16; 5],
+    ime: bool,
+}
+
+impl Entry {
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // "01:4000  21 00 C0  ld hl, $C000   AF=0180 BC=0013 ..."
+    pub fn format(&self) -> String {
+        let instr = Instruction::from_bytes(self.pc, &self.bytes);
+        let line = disasm::format_line(self.bank, &instr, &self.bytes[..disasm::length(&instr)]);
+        format!("{:<40} AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} IME={}",
+                line, self.regs[0], self.regs[1], self.regs[2], self.regs[3], self.regs[4],
+                self.ime as u8)
+    }
+}
+
+// Ring buffer of the last HISTORY_SIZE instructions, oldest first
+pub struct History {
+    entries: VecDeque<Entry>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: VecDeque::with_capacity(HISTORY_SIZE),
+        }
+    }
+
+    // called before the instruction at PC runs
+    pub fn record(&mut self, dmg: &Dmg) {
+        let cpu = dmg.cpu();
+        let interconnect = dmg.interconnect();
+        let pc = cpu.current_pc();
+        if self.entries.len() == HISTORY_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            bank: current_bank(dmg, pc),
+            pc: pc,
+            bytes: [interconnect.peek(pc),
+                    interconnect.peek(pc.wrapping_add(1)),
+                    interconnect.peek(pc.wrapping_add(2))],
+            regs: [cpu.read_reg16(Reg16::AF), cpu.read_reg16(Reg16::BC),
+                   cpu.read_reg16(Reg16::DE), cpu.read_reg16(Reg16::HL),
+                   cpu.read_reg16(Reg16::SP)],
+            ime: cpu.ime,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last(&self) -> Option<&Entry> {
+        self.entries.back()
+    }
+
+    // the most recent `count`, oldest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().skip(self.entries.len().saturating_sub(count))
+    }
+}
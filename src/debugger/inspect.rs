@@ -0,0 +1,107 @@
+use std::fmt::Write;
+
+use dmg::{Cpu, Interconnect};
+use dmg::cpu::{Reg8, Reg16};
+
+// Each of these returns whole lines, for the REPL and for crash reports
+
+pub fn regs(cpu: &Cpu) -> String {
+    let mut out = String::new();
+    writeln!(out, "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X}",
+             cpu.read_reg16(Reg16::AF), cpu.read_reg16(Reg16::BC),
+             cpu.read_reg16(Reg16::DE), cpu.read_reg16(Reg16::HL),
+             cpu.read_reg16(Reg16::SP), cpu.read_reg16(Reg16::PC)).unwrap();
+    let f = cpu.read_reg(Reg8::F);
+    let flag = |bit: u8, name: char| if f & (1 << bit) != 0 { name } else { '-' };
+    writeln!(out, "Flags: {}{}{}{}  IME={} HALT={}",
+             flag(7, 'Z'), flag(6, 'N'), flag(5, 'H'), flag(4, 'C'),
+             cpu.ime as u8, cpu.halted as u8).unwrap();
+    out
+}
+
+// 16 bytes per row
+pub fn memory(interconnect: &Interconnect, addr: u16, count: usize) -> String {
+    let mut out = String::new();
+    let mut addr = addr;
+    let mut remaining = count;
+    while remaining > 0 {
+        let row = remaining.min(16);
+        let bytes: Vec<String> = (0..row)
+            .map(|i| format!("{:02X}", interconnect.peek(addr.wrapping_add(i as u16))))
+            .collect();
+        writeln!(out, "{:04X}: {}", addr, bytes.join(" ")).unwrap();
+        addr = addr.wrapping_add(row as u16);
+        remaining -= row;
+    }
+    out
+}
+
+pub fn io(interconnect: &Interconnect) -> String {
+    let mut out = String::new();
+    let io = |addr: u16| interconnect.peek(addr);
+
+    let lcdc = io(0xFF40);
+    writeln!(out, "LCDC FF40 = {:02X}  lcd {}, window {} map {}, tiles {}, bg map {}, obj {} {}, bg {}",
+             lcdc,
+             on_off(lcdc, 7),
+             on_off(lcdc, 5),
+             if lcdc & (1 << 6) != 0 { "9C00" } else { "9800" },
+             if lcdc & (1 << 4) != 0 { "8000" } else { "8800" },
+             if lcdc & (1 << 3) != 0 { "9C00" } else { "9800" },
+             if lcdc & (1 << 2) != 0 { "8x16" } else { "8x8" },
+             on_off(lcdc, 1),
+             on_off(lcdc, 0)).unwrap();
+
+    let stat = io(0xFF41);
+    let mut sources = Vec::new();
+    if stat & (1 << 6) != 0 { sources.push("lyc"); }
+    if stat & (1 << 5) != 0 { sources.push("oam"); }
+    if stat & (1 << 4) != 0 { sources.push("vblank"); }
+    if stat & (1 << 3) != 0 { sources.push("hblank"); }
+    writeln!(out, "STAT FF41 = {:02X}  mode {}, ly==lyc {}, interrupts: {}",
+             stat, stat & 0b11, stat >> 2 & 1, list(&sources)).unwrap();
+
+    writeln!(out, "LY   FF44 = {:02X}  LYC  FF45 = {:02X}", io(0xFF44), io(0xFF45)).unwrap();
+    writeln!(out, "SCY  FF42 = {:02X}  SCX  FF43 = {:02X}", io(0xFF42), io(0xFF43)).unwrap();
+    writeln!(out, "WY   FF4A = {:02X}  WX   FF4B = {:02X}", io(0xFF4A), io(0xFF4B)).unwrap();
+    writeln!(out, "BGP  FF47 = {:02X}  OBP0 FF48 = {:02X}  OBP1 FF49 = {:02X}",
+             io(0xFF47), io(0xFF48), io(0xFF49)).unwrap();
+    writeln!(out, "DMA  FF46 = {:02X}  P1   FF00 = {:02X}", io(0xFF46), io(0xFF00)).unwrap();
+
+    let tac = io(0xFF07);
+    let frequency = match tac & 0b11 {
+        0 => "4096",
+        1 => "262144",
+        2 => "65536",
+        _ => "16384"
+    };
+    writeln!(out, "DIV  FF04 = {:02X}  TIMA FF05 = {:02X}  TMA  FF06 = {:02X}",
+             io(0xFF04), io(0xFF05), io(0xFF06)).unwrap();
+    writeln!(out, "TAC  FF07 = {:02X}  timer {}, {} Hz", tac, on_off(tac, 2), frequency).unwrap();
+
+    writeln!(out, "IF   FF0F = {:02X}  {}", io(0xFF0F), interrupts(io(0xFF0F))).unwrap();
+    writeln!(out, "IE   FFFF = {:02X}  {}", io(0xFFFF), interrupts(io(0xFFFF))).unwrap();
+    out
+}
+
+fn on_off(value: u8, bit: u8) -> &'static str {
+    if value & (1 << bit) != 0 { "on" } else { "off" }
+}
+
+fn interrupts(value: u8) -> String {
+    let names = ["vblank", "stat", "timer", "serial", "joypad"];
+    let set: Vec<&str> = names.iter()
+        .enumerate()
+        .filter(|&(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, &name)| name)
+        .collect();
+    list(&set)
+}
+
+fn list(names: &[&str]) -> String {
+    if names.is_empty() {
+        "none".into()
+    } else {
+        names.join(", ")
+    }
+}
@@ -1,72 +1,710 @@
+mod breakpoint;
 mod command;
+pub mod disasm;
+mod expr;
+pub mod dap;
+mod gdb;
+mod history;
+mod json;
+mod inspect;
+pub mod symbols;
+pub mod trace;
+pub mod trace_diff;
 
-use std::io::{stdin, stdout};
+use std::any::Any;
+use std::fs::{self, File};
+use std::io::{self, stdin, stdout};
 use std::io::prelude::*;
-use std::borrow::Cow;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use dmg::Dmg;
-use dmg::cpu::Instruction;
-use dmg::cpu::Opcode::*;
-use dmg::mem_map;
-use dmg::mem_map::Addr::*;
-use self::command::Command;
+use dmg::cpu::{FrameKind, Instruction, Opcode, Reg16};
+use dmg::fault::Fault;
+use dmg::watch::{Access, Source, Watchpoint, WatchHit};
+use self::breakpoint::{Breakpoint, Location, current_bank};
+use self::command::{Address, Command, Register};
+use self::expr::Expr;
+use self::history::History;
+use self::symbols::Symbols;
+use self::trace::{TraceOptions, Tracer};
 
+// instructions disassembled from the fault in a crash report
+const CRASH_DISASM_LINES: usize = 8;
+
+// The REPL is driven from the frontend's loop: stdin is read on its own
+// thread, and while running the frontend steps the emulator through
+// `run_step` so the window keeps handling events.
 pub struct Debugger {
     dmg: Dmg,
+    symbols: Option<Symbols>,
+
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: usize,
 
+    // REPL lines from stdin
+    input: Option<Receiver<String>>,
+    running: bool,
+    until: Option<RunUntil>,
+    quit: bool,
     last_command: Option<Command>,
+
+    gdb: Option<gdb::GdbServer>,
+    dap: Option<dap::DapServer>,
+    tracer: Option<Tracer>,
+    history: History,
 }
 
 impl Debugger {
-    pub fn new(dmg: Dmg) -> Debugger {
+    pub fn new(dmg: Dmg, symbols: Option<Symbols>, paused: bool) -> Debugger {
+        let mut debugger = Debugger::without_repl(dmg, symbols);
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let stdin = stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break
+                }
+            }
+        });
+
+        debugger.input = Some(receiver);
+        debugger.running = !paused;
+        if paused {
+            debugger.prompt();
+        }
+        debugger
+    }
+
+    // for when stdin isn't ours, e.g. DAP over stdio
+    pub fn without_repl(dmg: Dmg, symbols: Option<Symbols>) -> Debugger {
         Debugger {
             dmg: dmg,
+            symbols: symbols,
 
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+
+            input: None,
+            running: true,
+            until: None,
+            quit: false,
             last_command: None,
+
+            gdb: None,
+            dap: None,
+            tracer: None,
+            history: History::new(),
         }
     }
 
-    pub fn run(&mut self) {
+    pub fn dmg(&self) -> &Dmg {
+        &self.dmg
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    // break into the debugger, e.g. from a hotkey
+    pub fn interrupt(&mut self) {
+        if self.running {
+            println!();
+            self.stop(Stop::Interrupt);
+        }
+    }
+
+    // run one instruction while not paused, returning the cycles taken
+    pub fn run_step(&mut self) -> usize {
+        let returning = match self.until {
+            Some(RunUntil::Return(_)) => {
+                let pc = self.dmg.cpu().current_pc();
+                match Instruction::peek(pc, self.dmg.interconnect()).opcode() {
+                    Opcode::Ret(_) | Opcode::Reti => true,
+                    _ => false
+                }
+            },
+            _ => false
+        };
+        let (cycles, stop) = self.step_instruction();
+        let stop = stop.or_else(|| if self.until_reached(returning) {
+            Some(Stop::Reached)
+        } else {
+            None
+        });
+        if let Some(stop) = stop {
+            self.stop(stop);
+        }
+        cycles
+    }
+
+    // handle any commands typed or sent by a GDB client since the last call
+    pub fn poll_input(&mut self) {
+        self.poll_gdb();
+        self.poll_dap();
         loop {
-            print!("rustboy> ");
-            stdout().flush().unwrap();
-
-            let command = match (read_stdin().parse(), self.last_command) {
-                (Ok(Command::Repeat), Some(c)) => Ok(c),
-                (Ok(Command::Repeat), None) => Err("No last command".into()),
-                (Ok(c), _) => Ok(c),
-                (Err(e), _) => Err(e),
+            let line = match self.input.as_ref().map(|input| input.try_recv()) {
+                Some(Ok(line)) => line,
+                _ => break
             };
+            self.execute(line.trim());
+            if self.quit {
+                return;
+            }
+            if !self.running {
+                self.prompt();
+            }
+        }
+    }
+
+    pub fn start_trace(&mut self, path: &str, options: TraceOptions) -> io::Result<()> {
+        self.stop_trace();
+        self.tracer = Some(Tracer::create(path, options)?);
+        Ok(())
+    }
 
-            match command {
-                Ok(Command::Step(count)) => self.step(count),
-                Ok(Command::Exit) => break,
-                Ok(Command::Repeat) => unreachable!(),
-                Err(ref e) => println!("{}", e),
+    fn stop_trace(&mut self) {
+        if let Some(mut tracer) = self.tracer.take() {
+            if let Err(e) = tracer.flush() {
+                println!("Unable to write trace: {}", e);
             }
+        }
+    }
+
+    fn stop(&mut self, reason: Stop) {
+        self.running = false;
+        self.until = None;
+        self.disassemble(None, 1);
+        self.report_gdb_stop(reason);
+        self.report_dap_stop(reason);
+        self.prompt();
+    }
+
+    fn prompt(&self) {
+        if self.input.is_none() {
+            return;
+        }
+        print!("rustboy> ");
+        stdout().flush().unwrap();
+    }
+
+    fn execute(&mut self, line: &str) {
+        let command = match (line.parse(), self.last_command.clone()) {
+            (Ok(Command::Repeat), Some(c)) => Ok(c),
+            (Ok(Command::Repeat), None) => Err("No last command".into()),
+            (Ok(c), _) => Ok(c),
+            (Err(e), _) => Err(e),
+        };
+
+        match command {
+            Ok(Command::Step(count)) => self.step(count),
+            Ok(Command::Continue) => self.run_until(None),
+            Ok(Command::Next) => self.next(),
+            Ok(Command::Finish) => self.finish(),
+            Ok(Command::Until(ref addr)) => match self.resolve(addr) {
+                Ok(location) => self.run_until(Some(RunUntil::Addr(location))),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Command::Frame) => {
+                let ly = self.ly();
+                self.run_until(Some(RunUntil::Frame(ly)));
+            },
+            Ok(Command::Line) => {
+                let ly = self.ly();
+                self.run_until(Some(RunUntil::Line(ly)));
+            },
+            Ok(Command::Break(ref addr, ref condition)) => match self.resolve(addr) {
+                Ok(location) => { self.add_breakpoint(location, condition.clone()); },
+                Err(e) => println!("{}", e),
+            },
+            Ok(Command::Watch(access, ref start, ref end, value)) => {
+                let start = self.resolve(start);
+                let end = end.as_ref().map(|end| self.resolve(end));
+                match (start, end) {
+                    (Ok(start), None) => {
+                        self.add_watchpoint(access, start.addr, start.addr, value);
+                    },
+                    (Ok(start), Some(Ok(end))) => {
+                        self.add_watchpoint(access, start.addr, end.addr, value);
+                    },
+                    (Err(e), _) | (_, Some(Err(e))) => println!("{}", e),
+                }
+            },
+            Ok(Command::Delete(id)) => self.delete_breakpoint(id),
+            Ok(Command::List) => self.list_breakpoints(),
+            Ok(Command::Disasm(None, count)) => self.disassemble(None, count),
+            Ok(Command::Disasm(Some(ref addr), count)) => match self.resolve(addr) {
+                Ok(location) => self.disassemble(Some(location.addr), count),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Command::Regs) => print!("{}", inspect::regs(self.dmg.cpu())),
+            Ok(Command::Backtrace) => self.backtrace(),
+            Ok(Command::History(count)) => for entry in self.history.recent(count) {
+                println!("{}", entry.format());
+            },
+            Ok(Command::Set(reg, value)) => self.set_register(reg, value),
+            Ok(Command::Examine(ref addr, count)) => match self.resolve(addr) {
+                Ok(location) =>
+                    print!("{}", inspect::memory(self.dmg.interconnect(), location.addr, count)),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Command::Poke(ref addr, value)) => match self.resolve(addr) {
+                Ok(location) => self.dmg.poke(location.addr, value),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Command::Io) => print!("{}", inspect::io(self.dmg.interconnect())),
+            Ok(Command::Trace(ref path, ref options)) =>
+                match self.start_trace(path, options.clone()) {
+                    Ok(()) => println!("Tracing to {}", path),
+                    Err(e) => println!("Unable to trace to {}: {}", path, e),
+                },
+            Ok(Command::TraceOff) => self.stop_trace(),
+            Ok(Command::Print(ref expr)) => match expr.eval(&self.dmg, self.symbols.as_ref()) {
+                Ok(value) => println!("{} = {} (${:X})", expr, value, value),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Command::Exit) => self.quit = true,
+            Ok(Command::Repeat) => unreachable!(),
+            Err(ref e) => println!("{}", e),
+        }
+
+        self.last_command = command.ok();
+    }
+
+    // labels win over hex, so a "Add" label isn't read as $0ADD
+    fn resolve(&self, addr: &Address) -> Result<Location, String> {
+        let Address(ref name) = *addr;
+        self.symbols.as_ref()
+            .and_then(|symbols| symbols.lookup(name))
+            .or_else(|| command::parse_location(name))
+            .ok_or_else(|| format!("Unknown address or label: {}", name))
+    }
+
+    fn symbol(&self, bank: usize, addr: u16) -> Option<String> {
+        self.symbols.as_ref().and_then(|symbols| symbols.describe(bank, addr))
+    }
 
-            self.last_command = command.ok();
+    // "01:4000 (Main.loop)" when a label is known
+    fn describe_location(&self, bank: usize, addr: u16) -> String {
+        match self.symbol(bank, addr) {
+            Some(name) => format!("{:02X}:{:04X} ({})", bank, addr, name),
+            None => format!("{:02X}:{:04X}", bank, addr)
         }
     }
 
     pub fn step(&mut self, count: usize) {
         for _ in 0..count {
-            let current_pc = self.dmg.cpu().current_pc();
-            let addr = mem_map::map_addr(current_pc);
-            // let instr = Instruction::new(match addr {
-            //     Rom(offset) => self.dmg.interconnect().read_byte(offset as u16),
-            //     _ => panic!("Debugger can't inspect address: {:?}", addr),
-            // });
+            if self.step_instruction().1.is_some() {
+                break;
+            }
+        }
+        self.disassemble(None, 1);
+    }
+
+    fn run_until(&mut self, until: Option<RunUntil>) {
+        self.until = until;
+        self.running = true;
+    }
+
+    // calls and RSTs run until they return to the next instruction
+    fn next(&mut self) {
+        let pc = self.dmg.cpu().current_pc();
+        let instr = Instruction::peek(pc, self.dmg.interconnect());
+        match instr.opcode() {
+            Opcode::Call(..) | Opcode::Rst(_) => {
+                let ret = pc.wrapping_add(instr.bytes() as u16);
+                let sp = self.sp();
+                self.run_until(Some(RunUntil::Next(ret, sp)));
+            },
+            _ => self.step(1)
+        }
+    }
+
+    fn finish(&mut self) {
+        let sp = self.sp();
+        self.run_until(Some(RunUntil::Return(sp)));
+    }
+
+    fn until_reached(&mut self, returned: bool) -> bool {
+        let pc = self.dmg.cpu().current_pc();
+        let sp = self.sp();
+        let ly = self.ly();
+        match self.until {
+            None => false,
+            // back at the return address with the call's frame popped
+            Some(RunUntil::Next(addr, call_sp)) => pc == addr && sp >= call_sp,
+            // a RET or RETI that popped the current frame
+            Some(RunUntil::Return(frame_sp)) => returned && sp > frame_sp,
+            Some(RunUntil::Addr(location)) => location.matches(&self.dmg, pc),
+            Some(RunUntil::Frame(ref mut last_ly)) => {
+                let reached = ly == 144 && *last_ly != 144;
+                *last_ly = ly;
+                reached
+            },
+            Some(RunUntil::Line(start_ly)) => ly != start_ly,
+        }
+    }
+
+    fn sp(&self) -> u16 {
+        self.dmg.cpu().read_reg16(Reg16::SP)
+    }
+
+    fn ly(&self) -> u8 {
+        self.dmg.interconnect().peek(0xFF44)
+    }
+
+    // cycles taken, and the breakpoint or watchpoint hit if any. A panic
+    // inside the emulator stops with a crash report instead of exiting,
+    // strict mode faults and CPU lockups write one too.
+    fn step_instruction(&mut self) -> (usize, Option<Stop>) {
+        let pc = self.dmg.cpu().current_pc();
+        let was_locked = self.dmg.cpu().locked;
+        let mut trace_error = None;
+        let result = {
+            let dmg = &mut self.dmg;
+            let tracer = &mut self.tracer;
+            let history = &mut self.history;
+            let trace_error = &mut trace_error;
+            panic::catch_unwind(AssertUnwindSafe(move || dmg.step_with(|dmg| {
+                history.record(dmg);
+                if let Some(ref mut tracer) = *tracer {
+                    if let Err(e) = tracer.trace(dmg) {
+                        *trace_error = Some(e);
+                    }
+                }
+            })))
+        };
+        let cycles = match result {
+            Ok(cycles) => cycles,
+            Err(payload) => {
+                self.fault(&panic_message(&payload));
+                return (0, Some(Stop::Panic));
+            }
+        };
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.add_cycles(cycles);
+        }
+        if let Some(e) = trace_error {
+            println!("Unable to write trace, tracing stopped: {}", e);
+            self.tracer = None;
+        }
+        let watch_hit = self.check_watchpoints(pc);
+        let fault = self.dmg.take_fault();
+        // without strict mode a lockup isn't a fault, but the game just
+        // freezes, so it's reported all the same
+        let locked_up = !was_locked && self.dmg.cpu().locked;
+        let report = fault.or_else(|| if locked_up {
+            Some(Fault::IllegalOpcode(self.dmg.interconnect().peek(self.last_pc(pc))))
+        } else {
+            None
+        });
+        if let Some(report) = report {
+            let message = format!("{} at PC {:04X}", report, self.last_pc(pc));
+            self.fault(&message);
+        }
+        let stop = match (watch_hit, fault) {
+            (Some(hit), _) => Some(Stop::Watchpoint(hit)),
+            (None, Some(fault)) => Some(Stop::Fault(fault)),
+            (None, None) if self.check_breakpoints() => Some(Stop::Breakpoint),
+            (None, None) => None
+        };
+        (cycles, stop)
+    }
+
+    // the last instruction that started, which is the faulting one after
+    // an interrupt dispatch too
+    fn last_pc(&self, pc: u16) -> u16 {
+        self.history.last().map_or(pc, |entry| entry.pc())
+    }
+
+    fn fault(&self, message: &str) {
+        println!("Emulation fault: {}", message);
+        match self.write_crash_report(message) {
+            Ok(path) => println!("Crash report written to {}", path.display()),
+            Err(e) => println!("Unable to write crash report: {}", e),
+        }
+    }
+
+    // rustboy-crash-<time>.txt, and the machine at the fault in a .state
+    // file next to it for --load-state
+    fn write_crash_report(&self, message: &str) -> io::Result<PathBuf> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let path = PathBuf::from(format!("rustboy-crash-{}.txt", time));
+        let state_path = path.with_extension("state");
+        fs::write(&state_path, self.dmg.save_state())?;
+
+        let mut report = File::create(&path)?;
+        let title = String::from_utf8_lossy(self.dmg.cart().title()).into_owned();
+        writeln!(report, "Emulation fault: {}", message)?;
+        writeln!(report, "ROM: {}", title.trim_end_matches('\0'))?;
+        writeln!(report, "Save state: {}", state_path.display())?;
+
+        writeln!(report, "\nRegisters at the fault")?;
+        write!(report, "{}", inspect::regs(self.dmg.cpu()))?;
+
+        writeln!(report, "\nLast {} instructions, oldest first, with the registers before each",
+                 self.history.len())?;
+        for entry in self.history.recent(history::HISTORY_SIZE) {
+            writeln!(report, "{}", entry.format())?;
+        }
+
+        let pc = self.last_pc(self.dmg.cpu().current_pc());
+        writeln!(report, "\nDisassembly from {:04X}", pc)?;
+        for line in self.disassembly(Some(pc), CRASH_DISASM_LINES) {
+            writeln!(report, "{}", line)?;
+        }
+
+        writeln!(report, "\nCall stack")?;
+        for line in self.backtrace_lines() {
+            writeln!(report, "{}", line)?;
+        }
+
+        writeln!(report, "\nI/O registers")?;
+        write!(report, "{}", inspect::io(self.dmg.interconnect()))?;
+        Ok(path)
+    }
+
+    fn check_watchpoints(&self, pc: u16) -> Option<WatchHit> {
+        match self.dmg.take_watch_hit() {
+            Some(hit) => {
+                let access = match hit.access {
+                    Access::Read => "read",
+                    _ => "write"
+                };
+                let source = match hit.source {
+                    Source::Cpu => "",
+                    Source::Dma => " (OAM DMA)"
+                };
+                if hit.access == Access::Read {
+                    println!("Watchpoint {}: {} {:04X} = {:02X} at PC {:04X}{}",
+                             hit.id, access, hit.addr, hit.new, pc, source);
+                } else {
+                    println!("Watchpoint {}: {} {:04X} {:02X} -> {:02X} at PC {:04X}{}",
+                             hit.id, access, hit.addr, hit.old, hit.new, pc, source);
+                }
+                Some(hit)
+            },
+            None => None
+        }
+    }
+
+    // a condition that can't be evaluated stops as if it were true
+    fn check_breakpoints(&self) -> bool {
+        let pc = self.dmg.cpu().current_pc();
+        let symbols = self.symbols.as_ref();
+        let hit = self.breakpoints.iter()
+            .filter(|b| b.location.matches(&self.dmg, pc))
+            .find(|b| match b.condition {
+                Some(ref condition) => match condition.eval(&self.dmg, symbols) {
+                    Ok(value) => value != 0,
+                    Err(e) => {
+                        println!("Error in condition of breakpoint {}: {}", b.id, e);
+                        true
+                    }
+                },
+                None => true
+            });
+        match hit {
+            Some(breakpoint) => {
+                println!("Breakpoint {} at {}", breakpoint.id, breakpoint);
+                true
+            },
+            None => false
+        }
+    }
+
+    fn add_breakpoint(&mut self, location: Location, condition: Option<Expr>) -> usize {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let breakpoint = Breakpoint {
+            id: id,
+            location: location,
+            condition: condition,
+        };
+        println!("Breakpoint {} at {}", id, breakpoint);
+        self.breakpoints.push(breakpoint);
+        id
+    }
+
+    fn disassemble(&self, addr: Option<u16>, count: usize) {
+        for line in self.disassembly(addr, count) {
+            println!("{}", line);
+        }
+    }
+
+    fn disassembly(&self, addr: Option<u16>, count: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let pc = self.dmg.cpu().current_pc();
+        let interconnect = self.dmg.interconnect();
+        let mut addr = addr.unwrap_or(pc);
+        for _ in 0..count {
+            let instr = Instruction::peek(addr, interconnect);
+            let bytes: Vec<u8> = (0..disasm::length(&instr))
+                .map(|i| interconnect.peek(addr.wrapping_add(i as u16)))
+                .collect();
+            let bank = current_bank(&self.dmg, addr);
+            if let Some(name) = self.symbols.as_ref().and_then(|s| s.name_at(bank, addr)) {
+                lines.push(format!("{}:", name));
+            }
+            let marker = if addr == pc { "=>" } else { "  " };
+            let line = disasm::format_line(bank, &instr, &bytes);
+            let target = disasm::branch_target(&instr)
+                .and_then(|target| self.symbol(current_bank(&self.dmg, target), target));
+            lines.push(match target {
+                Some(name) => format!("{} {:<40} ; {}", marker, line, name),
+                None => format!("{} {}", marker, line)
+            });
+            addr = addr.wrapping_add(bytes.len() as u16);
+        }
+        lines
+    }
+
+    // innermost first: where each frame is now, and who called it
+    fn backtrace(&self) {
+        for line in self.backtrace_lines() {
+            println!("{}", line);
+        }
+    }
 
-            // println!("{:018x}: {}", current_pc, instr);
+    fn backtrace_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let pc = self.dmg.cpu().current_pc();
+        let mut location = (current_bank(&self.dmg, pc), pc);
+        for (depth, frame) in self.dmg.cpu().call_stack().iter().rev().enumerate() {
+            let kind = match frame.kind {
+                FrameKind::Call => "call",
+                FrameKind::Rst => "rst",
+                FrameKind::Interrupt => "interrupt",
+            };
+            lines.push(format!("#{:<3}{} in {}, {} from {}",
+                               depth, self.describe_location(location.0, location.1),
+                               self.describe_location(frame.target_bank, frame.target),
+                               kind, self.describe_location(frame.caller_bank, frame.caller)));
+            location = (frame.caller_bank, frame.caller);
+        }
+        let depth = self.dmg.cpu().call_stack().len();
+        lines.push(format!("#{:<3}{}", depth, self.describe_location(location.0, location.1)));
+        lines
+    }
 
-            self.dmg.step();
+    fn set_register(&mut self, reg: Register, value: u16) {
+        match reg {
+            Register::R8(_) if value > 0xFF => println!("Value too large: {:X}", value),
+            Register::R8(reg) => self.dmg.cpu_mut().write_reg(reg, value as u8),
+            Register::R16(reg) => self.dmg.cpu_mut().write_reg16(reg, value),
+        }
+    }
+
+    fn add_watchpoint(&mut self, access: Access, start: u16, end: u16,
+                      value: Option<u8>) -> usize {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        let watchpoint = Watchpoint {
+            id: id,
+            start: start,
+            end: end,
+            access: access,
+            value: value,
+        };
+        println!("Watchpoint {}: {}", id, describe_watchpoint(&watchpoint));
+        self.dmg.add_watchpoint(watchpoint);
+        id
+    }
+
+    // breakpoints and watchpoints share their numbering
+    fn delete_breakpoint(&mut self, id: Option<usize>) {
+        match id {
+            Some(id) => {
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|b| b.id != id);
+                if self.breakpoints.len() == count
+                    && !self.dmg.remove_watchpoint(id) {
+                    println!("No breakpoint number {}", id);
+                }
+            },
+            None => {
+                self.breakpoints.clear();
+                let ids: Vec<usize> =
+                    self.dmg.watchpoints().iter().map(|w| w.id).collect();
+                for id in ids {
+                    self.dmg.remove_watchpoint(id);
+                }
+            }
+        }
+    }
+
+    fn list_breakpoints(&self) {
+        if self.breakpoints.is_empty() && self.dmg.watchpoints().is_empty() {
+            println!("No breakpoints");
+        }
+        for breakpoint in &self.breakpoints {
+            println!("{:<4}break {}", breakpoint.id, breakpoint);
+        }
+        for watchpoint in self.dmg.watchpoints() {
+            println!("{:<4}watch {}", watchpoint.id, describe_watchpoint(watchpoint));
         }
     }
 }
 
-fn read_stdin() -> String {
-    let mut input = String::new();
-    stdin().read_line(&mut input).unwrap();
-    input.trim().into()
+// why the emulator stopped running
+#[derive(Debug, Clone, Copy)]
+enum Stop {
+    Breakpoint,
+    Watchpoint(WatchHit),
+    Interrupt,
+    // a step, next, finish or until completed
+    Reached,
+    // strict mode caught the game doing something odd
+    Fault(Fault),
+    // the emulator panicked, see the crash report
+    Panic,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RunUntil {
+    // return address and SP at the call
+    Next(u16, u16),
+    // SP when finish was entered
+    Return(u16),
+    Addr(Location),
+    // LY last seen
+    Frame(u8),
+    Line(u8),
+}
+
+// what a panic was raised with, usually a &str or a String
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload.downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "unknown panic".into())
+    }
+}
+
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    let access = match watchpoint.access {
+        Access::Read => "r",
+        Access::Write => "w",
+        Access::ReadWrite => "rw"
+    };
+    let mut description = if watchpoint.start == watchpoint.end {
+        format!("{} {:04X}", access, watchpoint.start)
+    } else {
+        format!("{} {:04X}-{:04X}", access, watchpoint.start, watchpoint.end)
+    };
+    if let Some(value) = watchpoint.value {
+        description.push_str(&format!(" if value=={:02X}", value));
+    }
+    description
 }
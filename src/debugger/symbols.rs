@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::breakpoint::Location;
+
+// Labels from an RGBDS .sym file, one "BB:AAAA Name" per line
+pub struct Symbols {
+    by_name: HashMap<String, Location>,
+    // only ROMX addresses keep their bank, see `key`
+    by_addr: BTreeMap<(usize, u16), String>,
+}
+
+impl Symbols {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Symbols, String> {
+        let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(|e| e.to_string())?;
+        Symbols::parse(&contents)
+    }
+
+    // <rom>.sym next to the ROM: game.sym, then game.gb.sym
+    pub fn load_for_rom<P: AsRef<Path>>(rom: P) -> Option<(PathBuf, Symbols)> {
+        let rom = rom.as_ref();
+        let mut appended = rom.as_os_str().to_owned();
+        appended.push(".sym");
+        let candidates = [rom.with_extension("sym"), PathBuf::from(appended)];
+        candidates.iter()
+            .filter(|path| path.exists())
+            .filter_map(|path| match Symbols::load(path) {
+                Ok(symbols) => Some((path.clone(), symbols)),
+                Err(e) => {
+                    println!("Unable to load symbols from {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .next()
+    }
+
+    fn parse(contents: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols {
+            by_name: HashMap::new(),
+            by_addr: BTreeMap::new(),
+        };
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (location, name) = match (parts.next(), parts.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => return Err(format!("line {}: expected BB:AAAA Name", number + 1))
+            };
+            let mut location = location.splitn(2, ':');
+            let bank = location.next().and_then(|b| usize::from_str_radix(b, 16).ok());
+            let addr = location.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+            let (bank, addr) = match (bank, addr) {
+                (Some(bank), Some(addr)) => (bank, addr),
+                _ => return Err(format!("line {}: invalid address", number + 1))
+            };
+            let location = Location { bank: rom_bank(bank, addr), addr: addr };
+            symbols.by_name.insert(name.into(), location);
+            symbols.by_addr.entry(key(bank, addr)).or_insert_with(|| name.into());
+        }
+        Ok(symbols)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Location> {
+        self.by_name.get(name).cloned()
+    }
+
+    pub fn name_at(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.by_addr.get(&key(bank, addr)).map(|name| name.as_str())
+    }
+
+    // closest label at or before addr in the same bank, as "Label+offset"
+    pub fn describe(&self, bank: usize, addr: u16) -> Option<String> {
+        let (bank, _) = key(bank, addr);
+        self.by_addr.range((bank, 0)..(bank, addr.saturating_add(1)))
+            .next_back()
+            .filter(|&(&(_, start), _)| same_region(start, addr))
+            .map(|(&(_, start), name)| if start == addr {
+                name.clone()
+            } else {
+                format!("{}+{:X}", name, addr - start)
+            })
+    }
+}
+
+fn rom_bank(bank: usize, addr: u16) -> Option<usize> {
+    match addr {
+        0x4000 ..= 0x7FFF => Some(bank),
+        _ => None
+    }
+}
+
+// only switchable ROM needs the bank to tell labels apart
+fn key(bank: usize, addr: u16) -> (usize, u16) {
+    (rom_bank(bank, addr).unwrap_or(0), addr)
+}
+
+// don't describe a RAM address relative to a ROM label
+fn same_region(a: u16, b: u16) -> bool {
+    let region = |addr: u16| match addr {
+        0x0000 ..= 0x3FFF => 0,
+        0x4000 ..= 0x7FFF => 1,
+        0x8000 ..= 0x9FFF => 2,
+        0xA000 ..= 0xBFFF => 3,
+        0xC000 ..= 0xDFFF => 4,
+        _ => 5
+    };
+    region(a) == region(b)
+}
@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+use dmg::Dmg;
+use dmg::cpu::{Reg8, Reg16};
+use super::breakpoint::current_bank;
+
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    // only instructions at these addresses, inclusive
+    pub range: Option<(u16, u16)>,
+    // only instructions in this ROM bank
+    pub bank: Option<usize>,
+    // extra columns after PCMEM
+    pub ly: bool,
+    pub cycles: bool,
+}
+
+// One line per instruction in the gameboy-doctor format, written before
+// the instruction runs
+pub struct Tracer {
+    out: BufWriter<File>,
+    options: TraceOptions,
+    // T-cycles since tracing started
+    cycles: u64,
+}
+
+impl Tracer {
+    pub fn create(path: &str, options: TraceOptions) -> io::Result<Tracer> {
+        Ok(Tracer {
+            out: BufWriter::new(File::create(path)?),
+            options,
+            cycles: 0,
+        })
+    }
+
+    pub fn trace(&mut self, dmg: &Dmg) -> io::Result<()> {
+        let pc = dmg.cpu().current_pc();
+        if let Some((start, end)) = self.options.range {
+            if pc < start || pc > end {
+                return Ok(());
+            }
+        }
+        if let Some(bank) = self.options.bank {
+            if current_bank(dmg, pc) != bank {
+                return Ok(());
+            }
+        }
+        write!(self.out, "{}", state_line(dmg))?;
+        if self.options.ly {
+            write!(self.out, " LY:{:02X}", dmg.interconnect().peek(0xFF44))?;
+        }
+        if self.options.cycles {
+            write!(self.out, " CYC:{}", self.cycles)?;
+        }
+        writeln!(self.out)
+    }
+
+    // M-cycles, as returned by Dmg::step
+    pub fn add_cycles(&mut self, cycles: usize) {
+        self.cycles += cycles as u64 * 4;
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+pub fn state_line(dmg: &Dmg) -> String {
+    let cpu = dmg.cpu();
+    let pc = cpu.current_pc();
+    let interconnect = dmg.interconnect();
+    let pcmem: Vec<String> = (0..4)
+        .map(|i| format!("{:02X}", interconnect.peek(pc.wrapping_add(i))))
+        .collect();
+    format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{}",
+            cpu.read_reg(Reg8::A), cpu.read_reg(Reg8::F),
+            cpu.read_reg(Reg8::B), cpu.read_reg(Reg8::C),
+            cpu.read_reg(Reg8::D), cpu.read_reg(Reg8::E),
+            cpu.read_reg(Reg8::H), cpu.read_reg(Reg8::L),
+            cpu.read_reg16(Reg16::SP), pc, pcmem.join(","))
+}
+
+// "START-END" in hex, as given to --trace-pc
+pub fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let mut parts = s.splitn(2, '-');
+    let start = parts.next().and_then(parse_hex);
+    let end = parts.next().and_then(parse_hex);
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Some((start, end)),
+        _ => None
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    let digits = s.strip_prefix("0x")
+        .or_else(|| s.strip_prefix('$'))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).ok()
+}
@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use dmg::Dmg;
+use dmg::cpu::Instruction;
+use super::breakpoint::current_bank;
+use super::disasm;
+use super::trace;
+
+// matching lines shown before a divergence
+const CONTEXT: usize = 8;
+// steps without executing an instruction before giving up on a HALT
+const MAX_IDLE_STEPS: usize = 0x100000;
+
+// Runs without a window, comparing the state before each instruction with
+// a reference trace line. Only columns both sides have are compared, except
+// cycle counts, which depend on where each trace started. Returns whether
+// the whole reference matched.
+pub fn run(mut dmg: Dmg, reference: &str) -> Result<bool, String> {
+    let file = File::open(reference).map_err(|e| format!("{}: {}", reference, e))?;
+    let mut context: VecDeque<(usize, String, String)> = VecDeque::new();
+    let mut count = 0;
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let expected = line.map_err(|e| format!("{}: {}", reference, e))?;
+        if expected.trim().is_empty() {
+            continue;
+        }
+
+        let (actual, text) = match next_state(&mut dmg) {
+            Some(state) => state,
+            None => {
+                println!("CPU stopped executing before line {} of {}", number + 1, reference);
+                print_context(&context);
+                return Ok(false);
+            }
+        };
+        let differences = compare(&expected, &actual);
+        if !differences.is_empty() {
+            println!("Divergence at line {} of {} after {} instructions",
+                     number + 1, reference, count);
+            print_context(&context);
+            println!("expected: {}", expected.trim());
+            println!("     got: {}", actual);
+            for &(ref key, ref want, ref got) in &differences {
+                println!("  {:<6} expected {}, got {}", key, want, got);
+            }
+            return Ok(false);
+        }
+
+        context.push_back((number + 1, actual, text));
+        if context.len() > CONTEXT {
+            context.pop_front();
+        }
+        count += 1;
+    }
+
+    println!("No divergence in {} instructions", count);
+    Ok(true)
+}
+
+// run until the CPU is about to execute an instruction, then take its
+// state line and disassembly and step over it
+fn next_state(dmg: &mut Dmg) -> Option<(String, String)> {
+    for _ in 0..MAX_IDLE_STEPS {
+        let mut state = None;
+        dmg.step_with(|dmg| {
+            let interconnect = dmg.interconnect();
+            let line = format!("{} LY:{:02X}", trace::state_line(dmg), interconnect.peek(0xFF44));
+            let pc = dmg.cpu().current_pc();
+            let instr = Instruction::peek(pc, interconnect);
+            let bytes: Vec<u8> = (0..disasm::length(&instr))
+                .map(|i| interconnect.peek(pc.wrapping_add(i as u16)))
+                .collect();
+            let text = disasm::format_line(current_bank(dmg, pc), &instr, &bytes);
+            state = Some((line, text));
+        });
+        if state.is_some() {
+            return state;
+        }
+    }
+    None
+}
+
+fn print_context(context: &VecDeque<(usize, String, String)>) {
+    for &(number, ref line, ref text) in context {
+        println!("{:>8}  {}", number, line);
+        println!("          {}", text);
+    }
+}
+
+// (column, expected, got) for each shared column that differs
+fn compare(expected: &str, actual: &str) -> Vec<(String, String, String)> {
+    let actual: Vec<(&str, &str)> = columns(actual).collect();
+    columns(expected)
+        .filter(|&(key, _)| key != "CYC")
+        .filter_map(|(key, want)| {
+            actual.iter()
+                .find(|&&(k, _)| k == key)
+                .and_then(|&(_, got)| if got.eq_ignore_ascii_case(want) {
+                    None
+                } else {
+                    Some((key.to_string(), want.to_string(), got.to_string()))
+                })
+        })
+        .collect()
+}
+
+// "KEY:VALUE" columns
+fn columns<'a>(line: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+    line.split_whitespace().filter_map(|column| {
+        let mut parts = column.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => Some((key, value)),
+            _ => None
+        }
+    })
+}
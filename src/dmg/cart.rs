@@ -39,6 +39,24 @@ impl Cart {
         }
     }
 
+    pub fn sgb_supported(&self) -> bool {
+        self.header.sgb_flag == SgbFlag::Yes
+    }
+
+    pub fn title(&self) -> &[u8] {
+        &self.header.title
+    }
+
+    // checksum the CGB boot ROM uses to pick a palette for DMG games,
+    // only computed for Nintendo-published titles
+    pub fn title_checksum(&self) -> Option<u8> {
+        if self.header.nintendo_licensee {
+            Some(self.header.title.iter().fold(0, |sum, &b| sum.wrapping_add(b)))
+        } else {
+            None
+        }
+    }
+
     pub fn rom_read_byte(&self, offset: usize) -> u8 {
         match self.header.cart_type {
             Mbc::None => self.rom[offset],
@@ -55,7 +73,7 @@ impl Cart {
         if offset < 0x4000 {
             LittleEndian::read_u16(&self.rom[offset..])
         } else {
-            let bank_offset = 0x4000 * (self.rom_bank.saturating_sub(1)) as usize;
+            let bank_offset = 0x4000 * (self.rom_bank() - 1);
             LittleEndian::read_u16(&self.rom[offset+bank_offset..])
         }
     }
@@ -65,6 +83,38 @@ impl Cart {
         self.ram[offset]
     }
 
+    // ignores the RAM enable
+    pub fn ram_peek(&self, offset: usize) -> u8 {
+        self.ram.get(offset).cloned().unwrap_or(0xFF)
+    }
+
+    pub fn ram_poke(&mut self, offset: usize, value: u8) {
+        if let Some(byte) = self.ram.get_mut(offset) {
+            *byte = value;
+        }
+    }
+
+    // reads past the end of the ROM give 0xFF
+    pub fn rom_peek(&self, offset: usize) -> u8 {
+        self.rom.get(self.rom_addr(offset)).cloned().unwrap_or(0xFF)
+    }
+
+    pub fn rom_poke(&mut self, offset: usize, value: u8) {
+        let addr = self.rom_addr(offset);
+        if let Some(byte) = self.rom.get_mut(addr) {
+            *byte = value;
+        }
+    }
+
+    // where a 0x0000-0x7FFF offset is in the ROM image, for the bank mapped now
+    fn rom_addr(&self, offset: usize) -> usize {
+        if offset < 0x4000 {
+            offset
+        } else {
+            offset + 0x4000 * (self.rom_bank() - 1)
+        }
+    }
+
     pub fn ram_read_word(&self, offset: usize) -> u16 {
         if !self.ram_timer_enable { return 0xFFFF }
         LittleEndian::read_u16(&self.ram[offset..])
@@ -132,22 +182,53 @@ impl Cart {
         }
     }
 
+    // MBC registers as raw bytes, for save states
+    pub fn mbc_state(&self) -> [u8; 5] {
+        let mode = match self.rom_ram_mode {
+            RomRam::Rom => 0,
+            RomRam::Ram => 1
+        };
+        [self.ram_timer_enable as u8, self.rom_bank, self.rom_bank_hi,
+         self.ram_bank_rtc, mode]
+    }
+
+    pub fn set_mbc_state(&mut self, state: [u8; 5]) {
+        self.ram_timer_enable = state[0] != 0;
+        self.rom_bank = state[1];
+        self.rom_bank_hi = state[2];
+        self.ram_bank_rtc = state[3];
+        self.rom_ram_mode = match state[4] {
+            0 => RomRam::Rom,
+            _ => RomRam::Ram
+        };
+    }
+
+    // bank currently mapped at 0x4000-0x7FFF
+    pub fn rom_bank(&self) -> usize {
+        match self.header.cart_type {
+            Mbc::None => 1,
+            Mbc::Mbc1 |
+            Mbc::Mbc1Ram |
+            Mbc::Mbc1RamBat => {
+                let bank = if let RomRam::Ram = self.rom_ram_mode {
+                    self.rom_bank & 0b11111
+                } else {
+                    self.rom_bank
+                };
+                (bank as usize).max(1)
+            },
+            Mbc::Mbc3TimerRam |
+            Mbc::Mbc3RamBat => (self.rom_bank as usize).max(1),
+            Mbc::Mbc5RamBat =>
+                ((self.rom_bank_hi as usize & 1) << 8 | self.rom_bank as usize).max(1),
+        }
+    }
+
     fn mbc1_rom_read_byte(&self, offset: usize) -> u8 {
-        if offset < 0x4000 || self.rom_bank == 0 && self.rom_bank_hi == 0 {
+        if offset < 0x4000 {
             self.rom[offset]
         } else {
-            let bank = match self.header.cart_type {
-                Mbc::Mbc1 |
-                Mbc::Mbc1Ram |
-                Mbc::Mbc1RamBat =>
-                    if let RomRam::Ram = self.rom_ram_mode {
-                        self.rom_bank & 0b11111
-                    } else {
-                        self.rom_bank
-                    },
-                _ => self.rom_bank
-            };
-            let bank_offset = 0x4000 * (bank.saturating_sub(1)) as usize;
+            let bank_offset = 0x4000 * (self.rom_bank() - 1);
             self.rom[offset + bank_offset]
         }
     }
@@ -156,8 +237,7 @@ impl Cart {
         if offset < 0x4000 {
             self.rom[offset]
         } else {
-            let bank = (self.rom_bank_hi as u16) & 1 << 8 | self.rom_bank as u16;
-            let bank_offset = 0x4000 * (bank.saturating_sub(1)) as usize;
+            let bank_offset = 0x4000 * (self.rom_bank() - 1);
             self.rom[offset + bank_offset]
         }
     }
@@ -166,6 +246,7 @@ impl Cart {
 #[derive(Debug)]
 struct Header {
     title: Box<[u8]>,
+    nintendo_licensee: bool,
     cgb_flag: CgbFlag,
     sgb_flag: SgbFlag,
     cart_type: Mbc,
@@ -176,17 +257,25 @@ struct Header {
 impl Header {
     pub fn new(rom: &Box<[u8]>) -> Header {
         // TODO read this data from the rom
-        let title = rom[0x134..0x143].to_vec().into_boxed_slice();
         let cgb = match rom[0x143] {
             0x80 => CgbFlag::Capable,
             0xC0 => CgbFlag::Only,
             _ => CgbFlag::No
         };
+        // DMG titles use all 16 bytes, CGB titles end before the CGB flag
+        let title_end = if let CgbFlag::No = cgb { 0x144 } else { 0x143 };
+        let title = rom[0x134..title_end].to_vec().into_boxed_slice();
+        let nintendo_licensee = match rom[0x14B] {
+            0x01 => true,
+            0x33 => &rom[0x144..0x146] == b"01",
+            _ => false
+        };
         if let CgbFlag::Only = cgb {
             panic!("CGB-only games not supported!");
         }
-        let sgb = match rom[0x146] {
-            0x03 => SgbFlag::Yes,
+        // the SGB BIOS also wants the old licensee code to be 0x33
+        let sgb = match (rom[0x146], rom[0x14B]) {
+            (0x03, 0x33) => SgbFlag::Yes,
             _ => SgbFlag::No
         };
         let cart = match rom[0x147] {
@@ -222,6 +311,7 @@ impl Header {
         };
         Header {
             title: title,
+            nintendo_licensee: nintendo_licensee,
             cgb_flag: cgb,
             sgb_flag: sgb,
             cart_type: cart,
@@ -249,7 +339,7 @@ enum CgbFlag {
     Only
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum SgbFlag {
     No,
     Yes
@@ -0,0 +1,63 @@
+// Shadow of the calls the game has made, kept alongside the real stack so
+// the debugger can show a backtrace. Games manipulate the stack directly
+// (jump tables that push and RET, resetting SP, popping return addresses),
+// so frames are matched by their SP slot rather than trusted blindly.
+
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // PC of the CALL/RST, or the instruction an interrupt cut in before
+    pub caller: u16,
+    pub caller_bank: usize,
+    pub target: u16,
+    pub target_bank: usize,
+    // SP after the return address was pushed
+    pub sp: u16,
+}
+
+#[derive(Debug)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        // anything at or below the new slot has been overwritten
+        while self.frames.last().map_or(false, |top| top.sp <= frame.sp) {
+            self.frames.pop();
+        }
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // a RET/RETI popping the return address at sp
+    pub fn ret(&mut self, sp: u16) {
+        while self.frames.last().map_or(false, |top| top.sp < sp) {
+            self.frames.pop();
+        }
+        if self.frames.last().map_or(false, |top| top.sp == sp) {
+            self.frames.pop();
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use dmg::Interconnect;
+use dmg::fault::Fault;
 use super::opcode::{Opcode, Operand8, Addr, Reg8, Reg16, JF};
 use super::opcode::Opcode::*;
 use super::opcode::Operand8::*;
 use super::opcode::Reg8::*;
 use super::opcode::Reg16::*;
 use super::Instruction;
+use super::call_stack::{CallStack, Frame, FrameKind};
 
 #[derive(Debug)]
 pub struct Cpu {
@@ -20,17 +22,21 @@ pub struct Cpu {
     flag_reg: Flags,
     // Interrupt Master Enable
     pub ime: bool,
-    ime_next_cycle: bool,
+    // EI takes effect after the next instruction
+    pub ime_next_cycle: bool,
     // halted, waiting for interrupt
     pub halted: bool,
     // halted, bugged state, won't jump to interrupt
     pub halt_no_jump: bool,
     // stopped, waiting for button press
     pub stopped: bool,
+    // hung by an illegal opcode, only a reset gets it going again
+    pub locked: bool,
     // clock time of last instruction
     last_m: usize,
     // clock time total
     clock_m: usize,
+    call_stack: CallStack,
 }
 
 impl Cpu {
@@ -52,10 +58,12 @@ impl Cpu {
             halted: false,
             halt_no_jump: false,
             stopped: false,
+            locked: false,
             // clock time of last instruction
             last_m: 0,
             // clock time total
             clock_m: 0,
+            call_stack: CallStack::new(),
         }
     }
 
@@ -63,8 +71,34 @@ impl Cpu {
         self.reg_pc
     }
 
+    // innermost frame last
+    pub fn call_stack(&self) -> &[Frame] {
+        self.call_stack.frames()
+    }
+
+    fn push_frame(&mut self, kind: FrameKind, caller: u16,
+                  interconnect: &Interconnect) {
+        let bank = |addr: u16| match addr {
+            0x4000 ..= 0x7FFF => interconnect.cart().rom_bank(),
+            _ => 0
+        };
+        self.call_stack.push(Frame {
+            kind: kind,
+            caller: caller,
+            caller_bank: bank(caller),
+            target: self.reg_pc,
+            target_bank: bank(self.reg_pc),
+            sp: self.reg_sp,
+        });
+    }
+
+    // halted, stopped or locked up rather than executing instructions
+    pub fn waiting(&self) -> bool {
+        self.halted || self.stopped || self.halt_no_jump || self.locked
+    }
+
     pub fn step(&mut self, interconnect: &mut Interconnect) -> usize {
-        if self.halted || self.stopped || self.halt_no_jump {
+        if self.waiting() {
             1 // wait for interrupt/button press
         } else {
             if self.ime_next_cycle {
@@ -145,7 +179,10 @@ impl Cpu {
                 self.ret(JF::Always, interconnect);
             },
             Rst(addr) => self.rst(addr, interconnect),
-            Undefined(op) => panic!("Undefined opcode: {:#x}", op)
+            Undefined(op) => {
+                self.locked = true;
+                interconnect.report_fault(Fault::IllegalOpcode(op));
+            }
         }
         (instr.cycles() as usize) + extra_jump_cycles
     }
@@ -155,8 +192,10 @@ impl Cpu {
         let cycles = 4;
         self.last_m = cycles;
         self.clock_m += cycles;
+        let caller = self.reg_pc;
         self.push(PC, interconnect);
         self.reg_pc = addr;
+        self.push_frame(FrameKind::Interrupt, caller, interconnect);
         cycles
     }
 
@@ -258,8 +297,11 @@ impl Cpu {
     fn call (&mut self, flag: JF, addr: u16, interconnect: &mut Interconnect) -> usize {
         let jump = self.jump_match(flag);
         if jump {
+            // PC is already past the 3 byte CALL
+            let caller = self.reg_pc.wrapping_sub(3);
             self.push(PC, interconnect);
             self.reg_pc = addr;
+            self.push_frame(FrameKind::Call, caller, interconnect);
             return 3;
         }
         0
@@ -356,8 +398,8 @@ impl Cpu {
 
     fn halt(&mut self, interconnect: &mut Interconnect) {
         if !self.ime {
-            let ie = interconnect.read_byte(0xffff);
-            let iflags = interconnect.read_byte(0xff0f);
+            let ie = interconnect.interrupts_enabled();
+            let iflags = interconnect.interrupt_flags();
             if ie & iflags & 0x1f == 0 {
                 self.halt_no_jump = true;
             } else {
@@ -554,6 +596,7 @@ impl Cpu {
         let jump = self.jump_match(flag);
         if jump {
             let sp = self.reg_sp;
+            self.call_stack.ret(sp);
             let addr = interconnect.read_word(sp);
             self.reg_pc = addr;
             self.reg_sp = sp + 2;
@@ -614,8 +657,10 @@ impl Cpu {
     }
 
     fn rst(&mut self, addr: u8, interconnect: &mut Interconnect) {
+        let caller = self.reg_pc.wrapping_sub(1);
         self.push(PC, interconnect);
         self.reg_pc = addr as u16;
+        self.push_frame(FrameKind::Rst, caller, interconnect);
     }
 
     fn sbc(&mut self, op: Operand8, interconnect: &mut Interconnect) {
@@ -756,7 +801,7 @@ impl Cpu {
         self.flag_reg.carry = false;
     }
 
-    fn read_reg(&self, reg: Reg8) -> u8 {
+    pub fn read_reg(&self, reg: Reg8) -> u8 {
         match reg {
             Reg8::A => self.reg_a,
             Reg8::B => self.reg_b,
@@ -769,7 +814,7 @@ impl Cpu {
         }
     }
 
-    fn read_reg16(&self, reg: Reg16) -> u16 {
+    pub fn read_reg16(&self, reg: Reg16) -> u16 {
         match reg {
             BC =>
                 (self.read_reg(Reg8::B) as u16) << 8 | self.read_reg(Reg8::C) as u16,
@@ -784,7 +829,7 @@ impl Cpu {
         }
     }
 
-    fn write_reg(&mut self, reg: Reg8, value: u8) {
+    pub fn write_reg(&mut self, reg: Reg8, value: u8) {
         match reg {
             Reg8::A => self.reg_a = value,
             Reg8::B => self.reg_b = value,
@@ -797,7 +842,7 @@ impl Cpu {
         }
     }
 
-    fn write_reg16(&mut self, reg: Reg16, value: u16) {
+    pub fn write_reg16(&mut self, reg: Reg16, value: u16) {
         let hi = (value >> 8) as u8;
         let lo = (value & 0xFF) as u8;
         match reg {
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use super::Opcode;
 use super::Opcode::*;
 use super::opcode::Reg16::*;
@@ -8,6 +10,7 @@ use super::opcode::Operand8::*;
 use dmg::Interconnect;
 
 pub struct Instruction {
+    pc: u16,
     opcode: Opcode,
     bytes: u8,
     cycles: u8
@@ -16,26 +19,55 @@ pub struct Instruction {
 impl Instruction {
     pub fn fetch(pc: u16, interconnect: &mut Interconnect) -> Instruction {
         let opcode = interconnect.read_byte(pc);
-        let imm16 = interconnect.read_word(pc + 1);
-        let (bytes, cycles, op) = decode(opcode, imm16);
-        Instruction {
-            opcode: op,
-            bytes: bytes,
-            cycles: cycles
-        }
+        let imm16 = Instruction::read_operands(pc + 1, opcode, interconnect);
+        Instruction::new(pc, opcode, imm16)
     }
 
     pub fn fetch_halt_bug(pc: u16, interconnect: &mut Interconnect) -> Instruction {
         let opcode = interconnect.read_byte(pc);
-        let imm16 = interconnect.read_word(pc);
+        let imm16 = Instruction::read_operands(pc, opcode, interconnect);
+        Instruction::new(pc, opcode, imm16)
+    }
+
+    // only the bytes the opcode takes go over the bus, so read watchpoints
+    // don't fire on whatever follows a shorter instruction
+    fn read_operands(addr: u16, opcode: u8, interconnect: &Interconnect) -> u16 {
+        let (bytes, _, _) = decode(opcode, 0);
+        match bytes {
+            3 => interconnect.read_word(addr),
+            2 => interconnect.read_byte(addr) as u16,
+            _ => 0
+        }
+    }
+
+    // for the debugger: no watchpoints, no bus side effects
+    pub fn peek(pc: u16, interconnect: &Interconnect) -> Instruction {
+        let bytes = [interconnect.peek(pc),
+                     interconnect.peek(pc.wrapping_add(1)),
+                     interconnect.peek(pc.wrapping_add(2))];
+        Instruction::from_bytes(pc, &bytes)
+    }
+
+    // decode from raw bytes, missing operand bytes read as 0
+    pub fn from_bytes(pc: u16, bytes: &[u8]) -> Instruction {
+        let byte = |i: usize| bytes.get(i).cloned().unwrap_or(0);
+        Instruction::new(pc, byte(0), (byte(2) as u16) << 8 | byte(1) as u16)
+    }
+
+    fn new(pc: u16, opcode: u8, imm16: u16) -> Instruction {
         let (bytes, cycles, op) = decode(opcode, imm16);
         Instruction {
+            pc: pc,
             opcode: op,
             bytes: bytes,
             cycles: cycles
         }
     }
 
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
     pub fn opcode(&self) -> Opcode {
         self.opcode
     }
@@ -49,6 +81,21 @@ impl Instruction {
     }
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.opcode {
+            Jr(cond, e) => {
+                let target = self.pc.wrapping_add(2).wrapping_add(e as u16);
+                match cond {
+                    JF::Always => write!(f, "jr ${:04X}", target),
+                    _ => write!(f, "jr {}, ${:04X}", cond, target)
+                }
+            },
+            opcode => write!(f, "{}", opcode)
+        }
+    }
+}
+
 fn decode(op: u8, imm16: u16) -> (u8, u8, Opcode) {
     let d8 = imm16 as u8;
     let r8 = d8 as i8;
@@ -1,7 +1,9 @@
+mod call_stack;
 mod cpu;
 mod instruction;
 mod opcode;
 
+pub use self::call_stack::FrameKind;
 pub use self::cpu::Cpu;
 pub use self::instruction::Instruction;
-pub use self::opcode::Opcode;
+pub use self::opcode::{Opcode, Reg8, Reg16};
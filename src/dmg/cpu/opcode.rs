@@ -1,3 +1,7 @@
+use std::fmt;
+
+use self::Opcode::*;
+
 #[derive(Debug, Copy, Clone)]
 pub enum Opcode {
     Ld(Operand8, Operand8),
@@ -104,3 +108,143 @@ pub enum JF { // Jump flags
     NZ,
     NC
 }
+
+// RGBDS syntax. Relative jumps are written from the start of the
+// instruction; Instruction resolves them to their target.
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Ld(Operand8::Mem(Addr::FF_C), src) => write!(f, "ldh [c], {}", src),
+            Ld(dest, Operand8::Mem(Addr::FF_C)) => write!(f, "ldh {}, [c]", dest),
+            Ld(Operand8::Mem(Addr::FF(n)), src) =>
+                write!(f, "ldh [${:04X}], {}", 0xFF00 | n as u16, src),
+            Ld(dest, Operand8::Mem(Addr::FF(n))) =>
+                write!(f, "ldh {}, [${:04X}]", dest, 0xFF00 | n as u16),
+            Ld(dest, src) => write!(f, "ld {}, {}", dest, src),
+            Ld16(reg, nn) => write!(f, "ld {}, ${:04X}", reg, nn),
+            LdnnSp(nn) => write!(f, "ld [${:04X}], sp", nn),
+            LdSpHl => write!(f, "ld sp, hl"),
+            Push(reg) => write!(f, "push {}", reg),
+            Pop(reg) => write!(f, "pop {}", reg),
+            Add(op) => write!(f, "add a, {}", op),
+            Adc(op) => write!(f, "adc a, {}", op),
+            Sub(op) => write!(f, "sub a, {}", op),
+            Sbc(op) => write!(f, "sbc a, {}", op),
+            And(op) => write!(f, "and a, {}", op),
+            Xor(op) => write!(f, "xor a, {}", op),
+            Or(op) => write!(f, "or a, {}", op),
+            Cp(op) => write!(f, "cp a, {}", op),
+            Inc(op) => write!(f, "inc {}", op),
+            Dec(op) => write!(f, "dec {}", op),
+            Daa => write!(f, "daa"),
+            Cpl => write!(f, "cpl"),
+            AddHl(reg) => write!(f, "add hl, {}", reg),
+            Inc16(reg) => write!(f, "inc {}", reg),
+            Dec16(reg) => write!(f, "dec {}", reg),
+            AddSp(e) => write!(f, "add sp, {}", e),
+            LdHlSp(e) => write!(f, "ld hl, sp{:+}", e),
+            Rlca => write!(f, "rlca"),
+            Rla => write!(f, "rla"),
+            Rrca => write!(f, "rrca"),
+            Rra => write!(f, "rra"),
+            Rlc(op) => write!(f, "rlc {}", op),
+            Rl(op) => write!(f, "rl {}", op),
+            Rrc(op) => write!(f, "rrc {}", op),
+            Rr(op) => write!(f, "rr {}", op),
+            Sla(op) => write!(f, "sla {}", op),
+            Swap(op) => write!(f, "swap {}", op),
+            Sra(op) => write!(f, "sra {}", op),
+            Srl(op) => write!(f, "srl {}", op),
+            Bit(bit, op) => write!(f, "bit {}, {}", bit, op),
+            Set(bit, op) => write!(f, "set {}, {}", bit, op),
+            Res(bit, op) => write!(f, "res {}, {}", bit, op),
+            Ccf => write!(f, "ccf"),
+            Scf => write!(f, "scf"),
+            Nop => write!(f, "nop"),
+            Halt => write!(f, "halt"),
+            Stop => write!(f, "stop"),
+            Di => write!(f, "di"),
+            Ei => write!(f, "ei"),
+            Jmp(JF::Always, nn) => write!(f, "jp ${:04X}", nn),
+            Jmp(cond, nn) => write!(f, "jp {}, ${:04X}", cond, nn),
+            JmpHl => write!(f, "jp hl"),
+            Jr(JF::Always, e) => write!(f, "jr @{:+}", e as i16 + 2),
+            Jr(cond, e) => write!(f, "jr {}, @{:+}", cond, e as i16 + 2),
+            Call(JF::Always, nn) => write!(f, "call ${:04X}", nn),
+            Call(cond, nn) => write!(f, "call {}, ${:04X}", cond, nn),
+            Ret(JF::Always) => write!(f, "ret"),
+            Ret(cond) => write!(f, "ret {}", cond),
+            Reti => write!(f, "reti"),
+            Rst(n) => write!(f, "rst ${:02X}", n),
+            Undefined(op) => write!(f, "db ${:02X}", op),
+        }
+    }
+}
+
+impl fmt::Display for Operand8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand8::Reg(reg) => write!(f, "{}", reg),
+            Operand8::Imm(n) => write!(f, "${:02X}", n),
+            Operand8::Mem(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Addr::BC => write!(f, "[bc]"),
+            Addr::DE => write!(f, "[de]"),
+            Addr::HL => write!(f, "[hl]"),
+            Addr::HLD => write!(f, "[hl-]"),
+            Addr::HLI => write!(f, "[hl+]"),
+            Addr::FF_C => write!(f, "[c]"),
+            Addr::Imm(nn) => write!(f, "[${:04X}]", nn),
+            Addr::FF(n) => write!(f, "[${:04X}]", 0xFF00 | n as u16),
+        }
+    }
+}
+
+impl fmt::Display for Reg8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg8::A => "a",
+            Reg8::B => "b",
+            Reg8::C => "c",
+            Reg8::D => "d",
+            Reg8::E => "e",
+            Reg8::F => "f",
+            Reg8::H => "h",
+            Reg8::L => "l",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg16::AF => "af",
+            Reg16::BC => "bc",
+            Reg16::DE => "de",
+            Reg16::HL => "hl",
+            Reg16::SP => "sp",
+            Reg16::PC => "pc",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for JF {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            JF::Always => "",
+            JF::Z => "z",
+            JF::C => "c",
+            JF::NZ => "nz",
+            JF::NC => "nc",
+        };
+        write!(f, "{}", name)
+    }
+}
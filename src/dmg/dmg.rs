@@ -1,6 +1,13 @@
 use dmg::cpu::Cpu;
+use dmg::cart::Cart;
+use dmg::fault::Fault;
 use dmg::interconnect::Interconnect;
+use dmg::ppu::Layer;
+use dmg::sgb::{SGB_WIDTH, SGB_HEIGHT};
+use dmg::state;
+use dmg::watch::{Watchpoint, WatchHit};
 use Color;
+use Rgb;
 
 pub struct Dmg {
     cpu: Cpu,
@@ -19,16 +26,94 @@ impl Dmg {
         &self.cpu
     }
 
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.interconnect.poke(addr, value);
+    }
+
+    pub fn cart(&self) -> &Cart {
+        self.interconnect.cart()
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        state::save(&self.cpu, &self.interconnect)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        state::load(&mut self.cpu, &mut self.interconnect, data)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.interconnect.watchpoints()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.interconnect.add_watchpoint(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.interconnect.remove_watchpoint(id)
+    }
+
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.interconnect.take_watch_hit()
+    }
+
+    pub fn set_ly_stub(&mut self, enabled: bool) {
+        self.interconnect.set_ly_stub(enabled);
+    }
+
+    // stop on faults the hardware would shrug off
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.interconnect.set_strict(enabled);
+    }
+
+    pub fn take_fault(&self) -> Option<Fault> {
+        self.interconnect.take_fault()
+    }
+
+    pub fn set_fifo_renderer(&mut self, enabled: bool) {
+        self.interconnect.set_fifo_renderer(enabled);
+    }
+
     pub fn framebuffer(&self) -> &[Color] {
         self.interconnect.framebuffer()
     }
 
+    pub fn framebuffer_layers(&self) -> &[Layer] {
+        self.interconnect.framebuffer_layers()
+    }
+
+    // false while the LCD is off or hasn't finished its first frame
+    pub fn lcd_visible(&self) -> bool {
+        self.interconnect.lcd_visible()
+    }
+
+    pub fn sgb_framebuffer(&self) -> Option<&[Rgb]> {
+        self.interconnect.sgb_framebuffer()
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        match self.sgb_framebuffer() {
+            Some(_) => (SGB_WIDTH, SGB_HEIGHT),
+            None => (160, 144)
+        }
+    }
+
     pub fn interconnect(&self) -> &Interconnect {
         &self.interconnect
     }
 
-    pub fn step(&mut self) -> usize {
+    // `before` sees the state the next instruction runs in, after any
+    // interrupt dispatch; it isn't called while halted or stopped
+    pub fn step_with<F: FnOnce(&Dmg)>(&mut self, before: F) -> usize {
         let int_cycles = self.proc_interrupts();
+        if !self.cpu.waiting() {
+            before(self);
+        }
         let cycles = self.cpu.step(&mut self.interconnect);
         for _ in 0..(cycles + int_cycles) {
             self.interconnect.step(4);
@@ -37,8 +122,12 @@ impl Dmg {
     }
 
     fn proc_interrupts(&mut self) -> usize {
-        let int_flags = self.interconnect.read_byte(0xFF0F);
-        let en_flags = self.interconnect.read_byte(0xFFFF);
+        // a locked up CPU doesn't take interrupts either
+        if self.cpu.locked {
+            return 0;
+        }
+        let int_flags = self.interconnect.interrupt_flags();
+        let en_flags = self.interconnect.interrupts_enabled();
         for bit in 0..5 {
             let flagged = int_flags >> bit & 0b1 != 0;
             let enabled = en_flags >> bit & 0b1 != 0;
@@ -49,7 +138,7 @@ impl Dmg {
                     return 0;
                 }
                 if self.cpu.ime {
-                    self.interconnect.write_byte(0xFF0F, int_flags & !(1 << bit));
+                    self.interconnect.acknowledge_interrupt(bit);
                     return self.interrupt(bit);
                 }
             }
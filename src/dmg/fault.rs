@@ -0,0 +1,39 @@
+use std::fmt;
+
+// Things a correct game shouldn't do, emulated the way the hardware
+// handles them. In strict mode the bus keeps the first one for the
+// debugger to stop on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    // the CPU locks up until reset
+    IllegalOpcode(u8),
+    // reads back as 0xFF
+    WriteOnlyRead(u16),
+    // ignored
+    ReadOnlyWrite(u16, u8),
+    // lands on the MBC as two byte writes
+    RomWordWrite(u16, u16),
+    // open bus, reads back as 0xFF
+    UnusedRead(u16),
+    // ignored
+    UnusedWrite(u16, u8),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::IllegalOpcode(op) =>
+                write!(f, "illegal opcode {:02X}, CPU locked up", op),
+            Fault::WriteOnlyRead(addr) =>
+                write!(f, "read of write-only register {:04X}", addr),
+            Fault::ReadOnlyWrite(addr, value) =>
+                write!(f, "write of {:02X} to read-only register {:04X}", value, addr),
+            Fault::RomWordWrite(addr, value) =>
+                write!(f, "16-bit write of {:04X} to ROM at {:04X}", value, addr),
+            Fault::UnusedRead(addr) =>
+                write!(f, "read of unused address {:04X}", addr),
+            Fault::UnusedWrite(addr, value) =>
+                write!(f, "write of {:02X} to unused address {:04X}", value, addr),
+        }
+    }
+}
@@ -1,17 +1,32 @@
+use std::cell::Cell;
 use std::io::{self,Write};
 
 use byteorder::{LittleEndian, ByteOrder};
 
-use dmg::{Cart, Ppu, Apu, Timer}; // TODO more periphs?
+use dmg::{Cart, Ppu, Apu, Timer, Sgb}; // TODO more periphs?
+use dmg::fault::Fault;
 use dmg::mem_map::{self, Addr};
+use dmg::ppu::Layer;
+use dmg::watch::{Access, Source, Watchpoint, Watchpoints, WatchHit};
 use Color;
+use Rgb;
 
 const RAM_SIZE: usize = 0x2000;
+const OAM_SIZE: usize = 0xA0;
+// M-cycles between writing 0xFF46 and the first byte being copied
+const DMA_START_DELAY: usize = 1;
 
 pub struct Interconnect {
     ppu: Ppu,
     apu: Apu,
     timer: Timer,
+    sgb: Option<Sgb>,
+    watch: Option<Watchpoints>,
+    // LY always reads 0x90, as when gameboy-doctor logs were recorded
+    ly_stub: bool,
+    // in strict mode the first fault is kept until the debugger takes it
+    strict: bool,
+    fault: Cell<Option<Fault>>,
 
     in_bootrom: bool,
     boot: Box<[u8]>,
@@ -28,23 +43,34 @@ pub struct Interconnect {
     serial_shift_clock: SerialShift,
 
     iflags: u8, // TODO break up the bits for store
-    dma_addr: u8,
-    dma_buffer: u8,
-    dma_counter: u8,
+    dma_reg: u8,
+    // last byte copied, seen by the CPU on the bus the DMA is using
+    dma_byte: u8,
+    dma_source: Option<u16>,
+    dma_index: usize,
+    // requested transfer and its remaining start delay
+    dma_start: Option<(u16, usize)>,
 
     ie_reg: u8 // Interrupts Enable Register TODO break up bits
 }
 
 impl Interconnect {
     pub fn new(boot_rom: Box<[u8]>, cart_rom: Box<[u8]>) -> Interconnect {
+        let cart = Cart::new(cart_rom);
+        let sgb = if cart.sgb_supported() { Some(Sgb::new()) } else { None };
         Interconnect {
             ppu: Ppu::new(),
             apu: Apu::new(),
             timer: Timer::new(),
+            sgb: sgb,
+            watch: None,
+            ly_stub: false,
+            strict: false,
+            fault: Cell::new(None),
 
             in_bootrom: false,
             boot: boot_rom,
-            cart: Cart::new(cart_rom),
+            cart: cart,
 
             cgb_ram_bank: 0,
             ram: vec![0; RAM_SIZE].into_boxed_slice(),
@@ -62,19 +88,154 @@ impl Interconnect {
             serial_shift_clock: SerialShift::External,
 
             iflags: 0,
-            dma_addr: 0,
-            dma_buffer: 0,
-            dma_counter: 0xA0,
+            dma_reg: 0,
+            dma_byte: 0xFF,
+            dma_source: None,
+            dma_index: 0,
+            dma_start: None,
 
             ie_reg: 0x00,
         }
     }
 
+    pub fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    pub fn cart_mut(&mut self) -> &mut Cart {
+        &mut self.cart
+    }
+
+    pub fn palettes(&self) -> [u8; 3] {
+        self.ppu.palettes()
+    }
+
+    pub fn set_fifo_renderer(&mut self, enabled: bool) {
+        self.ppu.set_fifo_renderer(enabled);
+    }
+
     pub fn framebuffer(&self) -> &[Color] {
         self.ppu.framebuffer()
     }
 
+    pub fn framebuffer_layers(&self) -> &[Layer] {
+        self.ppu.framebuffer_layers()
+    }
+
+    pub fn lcd_visible(&self) -> bool {
+        self.ppu.lcd_visible()
+    }
+
+    pub fn sgb_framebuffer(&self) -> Option<&[Rgb]> {
+        self.sgb.as_ref().map(|sgb| sgb.framebuffer())
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        match self.watch {
+            Some(ref watch) => watch.list(),
+            None => &[]
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watch.get_or_insert_with(Watchpoints::new).add(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let removed = match self.watch {
+            Some(ref mut watch) => watch.remove(id),
+            None => false
+        };
+        if self.watch.as_ref().map_or(false, |watch| watch.is_empty()) {
+            self.watch = None;
+        }
+        removed
+    }
+
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch.as_ref().and_then(|watch| watch.take_hit())
+    }
+
+    pub fn set_ly_stub(&mut self, enabled: bool) {
+        self.ly_stub = enabled;
+    }
+
+    // IF and IE for the interrupt logic, which isn't a CPU access and
+    // stays out of watchpoints
+    pub fn interrupt_flags(&self) -> u8 {
+        self.iflags
+    }
+
+    pub fn interrupts_enabled(&self) -> u8 {
+        self.ie_reg
+    }
+
+    pub fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.iflags &= !(1 << bit);
+    }
+
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    pub fn report_fault(&self, fault: Fault) {
+        if self.strict && self.fault.get().is_none() {
+            self.fault.set(Some(fault));
+        }
+    }
+
+    pub fn take_fault(&self) -> Option<Fault> {
+        self.fault.replace(None)
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
+        let value = if self.dma_conflict(addr) {
+            match mem_map::map_addr(addr) {
+                Addr::Oam(_) | Addr::Unused => 0xFF,
+                _ => self.dma_byte
+            }
+        } else {
+            self.bus_read(addr)
+        };
+        if let Some(ref watch) = self.watch {
+            watch.check(addr, Access::Read, value, value, Source::Cpu);
+        }
+        value
+    }
+
+    // Debugger access: no watchpoints, faults, DMA conflicts, PPU mode
+    // blocking or MBC writes. Defined for every address, unused ones
+    // read 0xFF.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match mem_map::map_addr(addr) {
+            Addr::Rom(offset) if self.in_bootrom && offset < 0x100 =>
+                self.boot.get(offset).cloned().unwrap_or(0xFF),
+            Addr::Rom(offset) => self.cart.rom_peek(offset),
+            Addr::Vram(offset) => self.ppu.dma_read_vram(offset),
+            Addr::Oam(offset) => self.ppu.dma_read_oam(offset),
+            Addr::Xram(offset) => self.cart.ram_peek(offset),
+            Addr::Unused |
+            Addr::ApuChan1FreqLo |
+            Addr::ApuChan2FreqLo |
+            Addr::ApuChan3FreqLo => 0xFF,
+            _ => self.bus_read(addr)
+        }
+    }
+
+    // ROM writes patch the currently mapped bank, unused addresses ignore
+    // the write
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match mem_map::map_addr(addr) {
+            Addr::Rom(offset) => self.cart.rom_poke(offset, value),
+            Addr::Vram(offset) => self.ppu.poke_vram(offset, value),
+            Addr::Oam(offset) => self.ppu.dma_write_oam(offset, value),
+            Addr::Xram(offset) => self.cart.ram_poke(offset, value),
+            Addr::Unused | Addr::PpuLcdY => {},
+            _ => self.bus_write(addr, value)
+        }
+    }
+
+    fn bus_read(&self, addr: u16) -> u8 {
         match mem_map::map_addr(addr) {
             Addr::Rom(offset) => if self.in_bootrom && offset < 0x100 {
                 self.boot[offset]
@@ -86,10 +247,16 @@ impl Interconnect {
             Addr::Ram(offset) => self.ram[offset],
             Addr::Echo(offset) => self.ram[offset],
             Addr::Oam(offset) => self.ppu.read_oam(offset),
-            Addr::Unused => 0xFF,
+            Addr::Unused => {
+                self.report_fault(Fault::UnusedRead(addr));
+                0xFF
+            },
             Addr::Hram(offset) => self.hram[offset],
 
-            Addr::JoypadReg => 0xFF, // TODO Joypad input
+            Addr::JoypadReg => match self.sgb {
+                Some(ref sgb) => sgb.read_joypad(0xFF),
+                None => 0xFF, // TODO Joypad input
+            },
             Addr::SerialData => self.serial_byte, // TODO
             Addr::SerialControl => self.read_serial_control(),
             Addr::TimerDivReg => self.timer.read_div_reg(),
@@ -101,18 +268,18 @@ impl Interconnect {
             Addr::ApuChan1Sweep => self.apu.read_chan1_sweep(),
             Addr::ApuChan1WaveLength => self.apu.read_chan1_wavelength(),
             Addr::ApuChan1Envelope => self.apu.read_chan1_envelope(),
-            Addr::ApuChan1FreqLo => panic!("0xFF13 is write-only!"),
+            Addr::ApuChan1FreqLo => self.write_only(addr),
             Addr::ApuChan1FreqHi => self.apu.read_chan1_freq_hi(),
 
             Addr::ApuChan2WaveLength => self.apu.read_chan2_wavelength(),
             Addr::ApuChan2Envelope => self.apu.read_chan2_envelope(),
-            Addr::ApuChan2FreqLo => panic!("0xFF18 is write-only!"),
+            Addr::ApuChan2FreqLo => self.write_only(addr),
             Addr::ApuChan2FreqHi => self.apu.read_chan2_freq_hi(),
 
             Addr::ApuChan3Enable => self.apu.read_chan3_enable(),
             Addr::ApuChan3Length => self.apu.read_chan3_length(),
             Addr::ApuChan3Volume => self.apu.read_chan3_volume(),
-            Addr::ApuChan3FreqLo => panic!("0xFF1D is write-only!"),
+            Addr::ApuChan3FreqLo => self.write_only(addr),
             Addr::ApuChan3FreqHi => self.apu.read_chan3_freq_hi(),
             Addr::ApuWaveRam(offset) => self.apu.read_wave_pattern_ram(offset),
 
@@ -129,9 +296,10 @@ impl Interconnect {
             Addr::PpuStatusReg => self.ppu.read_lcd_stat(),
             Addr::PpuScrollY => self.ppu.scy,
             Addr::PpuScrollX => self.ppu.scx,
-            Addr::PpuLcdY => self.ppu.line,
+            Addr::PpuLcdY if self.ly_stub => 0x90,
+            Addr::PpuLcdY => self.ppu.ly(),
             Addr::PpuLcdYCompare => self.ppu.lyc,
-            Addr::PpuOamDma => self.dma_addr,
+            Addr::PpuOamDma => self.dma_reg,
             Addr::PpuBgPalette => 0xFF,    // TODO write only?
             Addr::PpuObj0Palette => 0xFF, // TODO write only?
             Addr::PpuObj1Palette => 0xFF, // TODO write only?
@@ -149,6 +317,11 @@ impl Interconnect {
     }
 
     pub fn read_word(&self, addr: u16) -> u16 {
+        if self.dma_conflict(addr) || self.dma_conflict(addr.wrapping_add(1))
+            || self.watch.is_some() {
+            return self.read_byte(addr) as u16
+                | (self.read_byte(addr.wrapping_add(1)) as u16) << 8;
+        }
         match mem_map::map_addr(addr) {
             Addr::Rom(offset) => if self.in_bootrom && offset < 0x100 {
                 LittleEndian::read_u16(&self.boot[offset..])
@@ -162,15 +335,29 @@ impl Interconnect {
             Addr::Echo(offset) =>
                 LittleEndian::read_u16(&self.ram[offset..]),
             Addr::Oam(offset) => self.ppu.read_oam16(offset),
-            Addr::Unused => 0xFFFF,
             Addr::Hram(offset) =>
                 LittleEndian::read_u16(&self.hram[offset..]),
 
-            _ => panic!("Reading 16 bits from IO Regs not supported!")
+            // IO registers and unused addresses a byte at a time
+            _ => self.bus_read(addr) as u16
+                | (self.bus_read(addr.wrapping_add(1)) as u16) << 8
         }
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if self.dma_conflict(addr) {
+            return;
+        }
+        if let Some(ref watch) = self.watch {
+            if watch.watching(addr, Access::Write) {
+                let old = self.peek(addr);
+                watch.check(addr, Access::Write, old, value, Source::Cpu);
+            }
+        }
+        self.bus_write(addr, value);
+    }
+
+    fn bus_write(&mut self, addr: u16, value: u8) {
         match mem_map::map_addr(addr) {
             Addr::Rom(offset) => self.cart.mbc_write_byte(offset, value),
             Addr::Vram(offset) => self.ppu.write_vram(offset, value),
@@ -178,10 +365,12 @@ impl Interconnect {
             Addr::Ram(offset) => self.ram[offset] = value,
             Addr::Echo(offset) => self.ram[offset] = value,
             Addr::Oam(offset) => self.ppu.write_oam(offset, value),
-            Addr::Unused => {},
+            Addr::Unused => self.report_fault(Fault::UnusedWrite(addr, value)),
             Addr::Hram(offset) => self.hram[offset] = value,
 
-            Addr::JoypadReg => {}, // TODO Joypad select
+            Addr::JoypadReg => if let Some(ref mut sgb) = self.sgb {
+                sgb.write_joypad(value);
+            }, // TODO Joypad select
             Addr::SerialData => {
                 self.serial_byte = value;
                 print!("{}", value as char);
@@ -227,12 +416,12 @@ impl Interconnect {
             Addr::PpuStatusReg => self.ppu.write_lcd_stat(value),
             Addr::PpuScrollY => self.ppu.scy = value,
             Addr::PpuScrollX => self.ppu.scx = value,
-            Addr::PpuLcdY => panic!("Can't change current scanline!"),
-            Addr::PpuLcdYCompare => self.ppu.lyc = value,
+            Addr::PpuLcdY => self.report_fault(Fault::ReadOnlyWrite(addr, value)),
+            Addr::PpuLcdYCompare => self.ppu.write_lyc(value),
             Addr::PpuOamDma => {
-                self.dma_addr = value;
-                self.dma_counter = 0;
-                self.dma();
+                // a transfer already running carries on until this one starts
+                self.dma_reg = value;
+                self.dma_start = Some(((value as u16) << 8, DMA_START_DELAY));
             }
             Addr::PpuBgPalette => self.ppu.write_bg_palette(value),
             Addr::PpuObj0Palette => self.ppu.write_obj0_palette(value),
@@ -251,8 +440,18 @@ impl Interconnect {
     }
 
     pub fn write_word(&mut self, addr: u16, value: u16) {
+        if self.dma_conflict(addr) || self.dma_conflict(addr.wrapping_add(1))
+            || self.watch.is_some() {
+            self.write_byte(addr, value as u8);
+            self.write_byte(addr.wrapping_add(1), (value >> 8) as u8);
+            return;
+        }
         match mem_map::map_addr(addr) {
-            Addr::Rom(offset) => panic!("Write word to MBC not supported"),
+            Addr::Rom(_) => {
+                self.report_fault(Fault::RomWordWrite(addr, value));
+                self.bus_write(addr, value as u8);
+                self.bus_write(addr.wrapping_add(1), (value >> 8) as u8);
+            },
             Addr::Vram(offset) => self.ppu.write_vram16(offset, value),
             Addr::Xram(offset) => self.cart.ram_write_word(offset, value),
             Addr::Ram(offset) =>
@@ -260,18 +459,20 @@ impl Interconnect {
             Addr::Echo(offset) =>
                 LittleEndian::write_u16(&mut self.ram[offset..], value),
             Addr::Oam(offset) => self.ppu.write_oam16(offset, value),
-            Addr::Unused => {},
             Addr::Hram(offset) =>
                 LittleEndian::write_u16(&mut self.hram[offset..], value),
 
-            _ => panic!("tried writing word to unrecognized address {:#x}", addr)
+            _ => {
+                self.bus_write(addr, value as u8);
+                self.bus_write(addr.wrapping_add(1), (value >> 8) as u8);
+            }
         }
     }
 
     pub fn step(&mut self, cycles: usize) {
-        // OAM DMA
-        if self.dma_counter < 0xA0 {
-            self.dma();
+        // OAM DMA, one byte per M-cycle
+        for _ in 0..cycles / 4 {
+            self.dma_cycle();
         }
 
         // Timer Interrupt
@@ -284,55 +485,71 @@ impl Interconnect {
         if self.ppu.line == 144 && self.ppu.enter_vblank {
             self.iflags |= 1 << 0;
             self.ppu.enter_vblank = false;
+            if let Some(ref mut sgb) = self.sgb {
+                if sgb.transfer_pending() {
+                    sgb.vram_transfer(&self.ppu.vram_transfer_data());
+                }
+                if self.ppu.lcd_visible() {
+                    sgb.render(self.ppu.framebuffer());
+                }
+            }
         }
 
-        // LCD Stat Interrupts
-        let stat = self.ppu.read_lcd_stat();
-        let coincidence_int = stat >> 6 & 1 != 0;
-        let mode2_int = stat >> 5 & 1 != 0;
-        let mode1_int = stat >> 4 & 1 != 0;
-        let mode0_int = stat >> 3 & 1 != 0;
-        let coincidence = stat >> 2 & 1 != 0;
-        let mode = stat & 0b11;
-        if coincidence && coincidence_int && self.ppu.coincidence_start {
-            self.ppu.coincidence_start = false;
+        // LCD Stat Interrupt
+        if self.ppu.stat_interrupt {
+            self.ppu.stat_interrupt = false;
             self.iflags |= 1 << 1;
         }
-        match mode {
-            0b00 => if mode0_int && self.ppu.enter_mode0 {
-                self.ppu.enter_mode0 = false;
-                self.iflags |= 1 << 1;
-            },
-            0b01 => if (mode1_int || mode2_int) && self.ppu.enter_mode1 {
-                self.ppu.enter_mode1 = false;
-                self.iflags |= 1 << 1;
-            },
-            0b10 => if mode2_int && self.ppu.enter_mode2 {
-                self.ppu.enter_mode2 = false;
-                self.iflags |= 1 << 1;
-            },
-            _ => {}
+    }
+
+    // only HRAM and the IO registers can be used while a transfer runs
+    fn dma_conflict(&self, addr: u16) -> bool {
+        self.dma_source.is_some() && addr < 0xFF00
+    }
+
+    fn dma_cycle(&mut self) {
+        if let Some((source, delay)) = self.dma_start {
+            if delay == 0 {
+                self.dma_start = None;
+                self.dma_source = Some(source);
+                self.dma_index = 0;
+            } else {
+                self.dma_start = Some((source, delay - 1));
+            }
+        }
+        if let Some(source) = self.dma_source {
+            let src = source + self.dma_index as u16;
+            let byte = self.dma_read(src);
+            if let Some(ref watch) = self.watch {
+                let dest = 0xFE00 + self.dma_index as u16;
+                watch.check(src, Access::Read, byte, byte, Source::Dma);
+                if watch.watching(dest, Access::Write) {
+                    let old = self.ppu.dma_read_oam(self.dma_index);
+                    watch.check(dest, Access::Write, old, byte, Source::Dma);
+                }
+            }
+            self.ppu.dma_write_oam(self.dma_index, byte);
+            self.dma_byte = byte;
+            self.dma_index += 1;
+            if self.dma_index == OAM_SIZE {
+                self.dma_source = None;
+            }
         }
     }
 
-    fn dma(&mut self) {
-        // TODO check this
-        // -1: Read(0)
-        // 0: Read(1) Write(0)
-        // n: Read(n+1) Write(n)
-        let addr = (self.dma_addr as u16) << 8;
-        let slice = match mem_map::map_addr(addr) {
-            Addr::Rom(offset) => &self.cart.rom[offset..],
-            Addr::Ram(offset) => &self.ram[offset..],
-            Addr::Vram(offset) => panic!("dma from vram not implemented"),
-            Addr::Xram(offset) => &self.cart.ram[offset..],
-            Addr::Echo(offset) => &self.ram[offset..],
-            _ => panic!("Can't DMA from addresses higher than 0xF100")
-        };
-        let x = self.dma_counter as usize;
-        self.ppu.write_oam(x, slice[x]);
-        // self.dma_buffer = slice[x];
-        self.dma_counter += 1;
+    fn dma_read(&self, addr: u16) -> u8 {
+        // sources above 0xDFFF read from work RAM
+        let addr = if addr >= 0xE000 { addr - 0x2000 } else { addr };
+        match mem_map::map_addr(addr) {
+            Addr::Vram(offset) => self.ppu.dma_read_vram(offset),
+            _ => self.bus_read(addr)
+        }
+    }
+
+    // the APU frequency low bytes
+    fn write_only(&self, addr: u16) -> u8 {
+        self.report_fault(Fault::WriteOnlyRead(addr));
+        0xFF
     }
 
     fn read_serial_control(&self) -> u8 {
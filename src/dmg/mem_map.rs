@@ -297,6 +297,7 @@ pub fn map_addr(addr: u16) -> Addr {
         CGB_RAM_BANK => Addr::CgbRamBank,
         IEREG => Addr::InterruptsEnable,
         0xFF7F => Addr::FF7F,
-        _ => panic!("Unrecognized address: {:#x}", addr)
+        // I/O addresses with no register behind them
+        _ => Addr::Unused
     }
 }
@@ -6,6 +6,10 @@ mod interconnect;
 mod cart;
 mod apu;
 mod timer;
+mod sgb;
+mod state;
+pub mod watch;
+pub mod fault;
 
 pub use self::dmg::Dmg;
 pub use self::cpu::Cpu;
@@ -14,3 +18,4 @@ pub use self::apu::Apu;
 pub use self::timer::Timer;
 pub use self::interconnect::Interconnect;
 pub use self::cart::Cart;
+pub use self::sgb::Sgb;
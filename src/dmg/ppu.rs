@@ -7,22 +7,37 @@ const SCREEN_WIDTH: usize = 160;
 const SCREEN_HEIGHT: usize = 144;
 const SCREEN_AREA: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
 const BLANK_SCREEN: [Color; SCREEN_AREA] = [Color::Off; SCREEN_AREA];
+const WX_MAX: u8 = 166;
+const BLANK_LAYERS: [Layer; SCREEN_AREA] = [Layer::Bg; SCREEN_AREA];
+const TILE_COUNT: usize = 384;
+const TILE_DATA_END: usize = TILE_COUNT * 16;
+const TILE_MAP0: usize = 0x1800;
+const TILE_MAP1: usize = 0x1C00;
+
+mod fifo;
 
-use std::cmp::Ordering;
 use byteorder::{ByteOrder, LittleEndian};
 use Color;
+use self::fifo::Fifo;
 
 pub struct Ppu {
     vram: Box<[u8]>,
-    oam: Box<[Sprite]>,
-    tileset: Box<[Tile]>,
-    tile_map0: Box<[u8]>,
-    tile_map1: Box<[u8]>,
+    oam: Box<[u8]>,
+    // decoded copies of the tile data in VRAM, redone when written
+    tiles: Box<[Tile]>,
+    dirty_tiles: Box<[bool]>,
+    any_dirty_tiles: bool,
 
     fb: Box<[Color]>,
+    fb_layers: Box<[Layer]>,
+    fifo: Option<Fifo>,
+    // sprites found by the OAM scan for the current line
+    line_sprites: Vec<(usize, Sprite)>,
     mode: Mode,
     modeclock: usize,
     pub line: u8, // LY: 160 lines
+    // LY as the CPU sees it, reads 0 for most of line 153
+    ly: u8,
     pub enter_vblank: bool,
     // LY Compare
     pub lyc: u8,
@@ -35,21 +50,29 @@ pub struct Ppu {
     win_display: bool, // bit 5
     win_tilemap_select: Tilemap, // bit 6
     lcd_enable: bool, // bit 7
+    // line 0 after the LCD is switched on has no OAM scan
+    first_line: bool,
+    // the first frame after switching the LCD on isn't shown
+    skip_frame: bool,
     // LCD STAT, make separate struct?
     coincidence_int: bool,
-    pub coincidence_start: bool,
     mode2oam_int: bool,
-    pub enter_mode2: bool,
     mode1vblank_int: bool,
-    pub enter_mode1: bool,
     mode0hblank_int: bool,
-    pub enter_mode0: bool,
+    coincidence: bool,
+    // all enabled STAT conditions OR'd together
+    stat_line: bool,
+    pub stat_interrupt: bool,
     // Scroll coords
     pub scy: u8,
     pub scx: u8,
     // Window coords
     pub wy: u8,
     pub wx: u8,
+    // internal window line counter
+    window_line: u8,
+    // LY matched WY at some point this frame
+    wy_triggered: bool,
     // background palette
     bgp: Palette,
     // Object palettes
@@ -61,26 +84,29 @@ impl Ppu {
     pub fn new() -> Ppu {
         Ppu {
             vram: Box::new([0; 0x2000]),
-            oam: Box::new([Sprite::new(); 40]),
-            tileset: Box::new([Tile::new(); 384]),
-            tile_map0: Box::new([0; 0x400]),
-            tile_map1: Box::new([0; 0x400]),
+            oam: Box::new([0; 0xA0]),
+            tiles: Box::new([Tile::new(); TILE_COUNT]),
+            dirty_tiles: Box::new([false; TILE_COUNT]),
+            any_dirty_tiles: false,
 
             fb: Box::new(BLANK_SCREEN),
+            fb_layers: Box::new(BLANK_LAYERS),
+            fifo: None,
+            line_sprites: Vec::with_capacity(10),
             mode: Mode::Oam,
             modeclock: 0,
             line: 0,
+            ly: 0,
             enter_vblank: false,
             lyc: 0,
 
             coincidence_int: false,
-            coincidence_start: false,
             mode2oam_int: false,
-            enter_mode2: false,
             mode1vblank_int: false,
-            enter_mode1: false,
             mode0hblank_int: false,
-            enter_mode0: false,
+            coincidence: false,
+            stat_line: false,
+            stat_interrupt: false,
 
             bg_display: true,  // bit 0
             obj_display: true,
@@ -90,12 +116,16 @@ impl Ppu {
             win_display: true,
             win_tilemap_select: Tilemap::Map0,
             lcd_enable: true, // bit 7
+            first_line: false,
+            skip_frame: false,
 
             scy: 0,
             scx: 0,
 
             wy: 0,
             wx: 0,
+            window_line: 0,
+            wy_triggered: false,
 
             // perhaps store these as single bytes, then convert to RGBA when needed
             bgp: Palette::new(),
@@ -108,36 +138,62 @@ impl Ppu {
         &self.fb
     }
 
+    pub fn framebuffer_layers(&self) -> &[Layer] {
+        &self.fb_layers
+    }
+
+    pub fn set_fifo_renderer(&mut self, enabled: bool) {
+        self.fifo = if enabled { Some(Fifo::new()) } else { None };
+    }
+
     pub fn step(&mut self, last_t: usize) {
         if !self.lcd_enable {
             return;
         }
+        if let Some(mut fifo) = self.fifo.take() {
+            for _ in 0..last_t {
+                fifo.dot(self);
+                let dot = fifo.line_dot();
+                self.update_ly(dot);
+                self.update_stat();
+            }
+            self.fifo = Some(fifo);
+            return;
+        }
         self.modeclock += last_t;
         match self.mode {
             Mode::Oam => {
                 if self.modeclock > 80 {
                     self.modeclock = 0;
+                    self.oam_scan();
                     self.mode = Mode::Vram;
                 }
             }
             Mode::Vram => {
                 if self.modeclock > 168 {
                     self.modeclock = 0;
-                    self.enter_mode0 = true;
                     self.draw_line();
                     self.mode = Mode::Hblank;
                 }
             }
+            Mode::Hblank if self.first_line => {
+                if self.modeclock > 80 {
+                    self.modeclock = 0;
+                    self.first_line = false;
+                    self.oam_scan();
+                    self.mode = Mode::Vram;
+                }
+            }
             Mode::Hblank => {
                 if self.modeclock > 200 {
                     self.modeclock = 0;
                     self.line += 1;
                     if self.line == 144 {
+                        self.window_line = 0;
+                        self.wy_triggered = false;
                         self.enter_vblank = true;
-                        self.enter_mode1 = true;
                         self.mode = Mode::Vblank;
                     } else {
-                        self.enter_mode2 = true;
                         self.mode = Mode::Oam;
                     }
                 }
@@ -147,125 +203,176 @@ impl Ppu {
                     self.modeclock = 0;
                     self.line += 1;
                     if self.line > 153 {
-                        self.enter_mode2 = true;
+                        self.skip_frame = false;
                         self.mode = Mode::Oam;
                         self.line = 0;
                     }
                 }
             }
         }
+        let dot = self.modeclock;
+        self.update_ly(dot);
+        self.update_stat();
+    }
+
+    pub fn lcd_visible(&self) -> bool {
+        self.lcd_enable && !self.skip_frame
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    // dot is the position within the current line
+    fn update_ly(&mut self, dot: usize) {
+        // LY goes back to 0 a few dots into line 153
+        self.ly = if self.line == 153 && dot >= 4 { 0 } else { self.line };
+    }
+
+    fn stat_conditions(&self, lyc: bool, mode2: bool, mode1: bool, mode0: bool)
+                       -> bool {
+        (lyc && self.coincidence) ||
+        match self.mode {
+            Mode::Oam => mode2,
+            Mode::Vblank => mode1,
+            Mode::Hblank => mode0,
+            Mode::Vram => false
+        }
+    }
+
+    // the interrupt is only requested when the line goes from low to high,
+    // so a condition that's already holding it high blocks the others
+    fn update_stat(&mut self) {
+        self.coincidence = self.ly == self.lyc;
+        let line = self.stat_conditions(self.coincidence_int,
+                                        self.mode2oam_int,
+                                        self.mode1vblank_int,
+                                        self.mode0hblank_int);
+        if line && !self.stat_line {
+            self.stat_interrupt = true;
+        }
+        self.stat_line = line;
+    }
+
+    pub fn write_lyc(&mut self, value: u8) {
+        self.lyc = value;
+        if self.lcd_enable {
+            self.update_stat();
+        }
     }
 
     pub fn read_vram(&self, addr: usize) -> u8 {
         match self.mode {
             Mode::Vram => 0xFF,
-            _ => match addr {
-                0x0000 ..= 0x17FF => {
-                    let tile = &self.tileset[addr / 16];
-                    tile.data[addr % 16]
-                },
-                0x1800 ..= 0x1BFF => self.tile_map0[addr - 0x1800],
-                _ => self.tile_map1[addr - 0x1C00]
-            }
+            _ => self.vram[addr]
         }
     }
 
     pub fn read_vram16(&self, addr: usize) -> u16 {
         match self.mode {
-            Mode::Hblank |
-            Mode::Vblank |
-            Mode::Oam => LittleEndian::read_u16(&self.vram[addr..]),
-            _ => 0xFFFF
+            Mode::Vram => 0xFFFF,
+            _ => LittleEndian::read_u16(&self.vram[addr..])
         }
     }
 
     pub fn write_vram(&mut self, addr: usize, value: u8) {
-        match self.mode {
-            Mode::Vram => return,
-            _ => match addr {
-                0x0000 ..= 0x17FF => {
-                    let tile = &mut self.tileset[addr / 16];
-                    tile.data[addr % 16] = value;
-                },
-                0x1800 ..= 0x1BFF => self.tile_map0[addr - 0x1800] = value,
-                _ => self.tile_map1[addr - 0x1C00] = value
-            }
+        if self.mode == Mode::Vram {
+            return;
         }
+        self.vram[addr] = value;
+        self.invalidate_tile(addr);
     }
 
     pub fn write_vram16(&mut self, addr: usize, value: u16) {
-        match self.mode {
-            Mode::Vram => return,
-            _ => LittleEndian::write_u16(&mut self.vram[addr..], value)
+        if self.mode == Mode::Vram {
+            return;
+        }
+        LittleEndian::write_u16(&mut self.vram[addr..], value);
+        self.invalidate_tile(addr);
+        self.invalidate_tile(addr + 1);
+    }
+
+    fn invalidate_tile(&mut self, addr: usize) {
+        if addr < TILE_DATA_END {
+            self.dirty_tiles[addr / 16] = true;
+            self.any_dirty_tiles = true;
+        }
+    }
+
+    // decode tiles written since the last line was drawn; VRAM can't be
+    // written during mode 3, so this only needs doing before each line
+    fn refresh_tiles(&mut self) {
+        if !self.any_dirty_tiles {
+            return;
+        }
+        for index in 0..TILE_COUNT {
+            if self.dirty_tiles[index] {
+                let data = &self.vram[index * 16..index * 16 + 16];
+                self.tiles[index] = Tile::decode(data);
+                self.dirty_tiles[index] = false;
+            }
         }
+        self.any_dirty_tiles = false;
+    }
+
+    fn sprite(&self, index: usize) -> Sprite {
+        Sprite::decode(&self.oam[index * 4..index * 4 + 4])
     }
 
     pub fn read_oam(&self, addr: usize) -> u8 {
         match self.mode {
             Mode::Hblank |
-            Mode::Vblank => {
-                let sprite_addr = addr / 4;
-                match addr % 4 {
-                    0 => self.oam[sprite_addr].y,
-                    1 => self.oam[sprite_addr].x,
-                    2 => self.oam[sprite_addr].tile,
-                    3 => {
-                        let sprite = self.oam[sprite_addr];
-                        let bit7 = if sprite.bg_prio { 1 << 7 } else { 0 };
-                        let bit6 = if sprite.x_flip { 1 << 6 } else { 0 };
-                        let bit5 = if sprite.y_flip { 1 << 5 } else { 0 };
-                        let bit4 = if sprite.palette { 1 << 4 } else { 0 };
-                        bit7 | bit6 | bit5 | bit4
-                    },
-                    _ => unreachable!()
-                }
-            },
+            Mode::Vblank => self.oam[addr],
             _ => 0xFF
         }
     }
 
     pub fn read_oam16(&self, addr: usize) -> u16 {
-        (self.read_oam(addr) as u16) << 8 | self.read_oam(addr + 1) as u16
+        match self.mode {
+            Mode::Hblank |
+            Mode::Vblank => LittleEndian::read_u16(&self.oam[addr..]),
+            _ => 0xFFFF
+        }
     }
 
     pub fn write_oam(&mut self, addr: usize, value: u8) {
         if self.mode == Mode::Vram || self.mode == Mode::Oam {
             return;
         }
-        let sprite_addr = addr / 4;
-        match addr % 4 {
-            0 => self.oam[sprite_addr].y = value.wrapping_sub(16),
-            1 => self.oam[sprite_addr].x = value.wrapping_sub(8),
-            2 => self.oam[sprite_addr].tile = value,
-            3 => {
-                let sprite = &mut self.oam[sprite_addr];
-                sprite.bg_prio = value >> 7 != 0;
-                sprite.y_flip = value >> 6 & 1 != 0;
-                sprite.x_flip = value >> 5 & 1 != 0;
-                sprite.palette = value >> 4 & 1 != 0;
-            },
-            _ => unreachable!()
-        }
+        self.oam[addr] = value;
     }
 
     pub fn write_oam16(&mut self, addr: usize, value: u16) {
-        self.write_oam(addr, (value >> 8) as u8);
-        self.write_oam(addr + 1, (value as u8) & 0xFF);
+        if self.mode == Mode::Vram || self.mode == Mode::Oam {
+            return;
+        }
+        LittleEndian::write_u16(&mut self.oam[addr..], value);
     }
 
-    pub fn dma_from_vram(&mut self, offset: usize) {
-        for x in 0x00 .. 0xA0 {
-            let byte = self.read_vram(offset + x);
-            self.write_oam(x, byte);
-        }
+    // OAM DMA isn't blocked by the PPU mode
+    pub fn dma_read_vram(&self, addr: usize) -> u8 {
+        self.vram[addr]
+    }
+
+    pub fn poke_vram(&mut self, addr: usize, value: u8) {
+        self.vram[addr] = value;
+        self.invalidate_tile(addr);
+    }
+
+    pub fn dma_read_oam(&self, addr: usize) -> u8 {
+        self.oam[addr]
+    }
+
+    pub fn dma_write_oam(&mut self, addr: usize, value: u8) {
+        self.oam[addr] = value;
     }
 
     pub fn read_lcd_ctrl(&self) -> u8 {
         let bit0 = if self.bg_display  { 1 << 0 } else { 0 };
         let bit1 = if self.obj_display { 1 << 1 } else { 0 };
         let bit2 = match self.obj_size {
-            SpriteSize::Normal => 1 << 2,
-            SpriteSize::DblHeight => 0
+            SpriteSize::Normal => 0,
+            SpriteSize::DblHeight => 1 << 2
         };
         let bit3 = match self.bg_tilemap_select {
             Tilemap::Map1 => 1 << 3,
@@ -308,10 +415,27 @@ impl Ppu {
         } else {
             Tilemap::Map0
         };
-        self.lcd_enable = value & (1 << 7) != 0;
-        if !self.lcd_enable {
+        let enable = value & (1 << 7) != 0;
+        if self.lcd_enable && !enable {
+            // the PPU sits in mode 0 on line 0 while the LCD is off
+            self.lcd_enable = false;
+            self.mode = Mode::Hblank;
+            self.modeclock = 0;
             self.line = 0;
-            self.fb = Box::new(BLANK_SCREEN);
+            self.ly = 0;
+            self.window_line = 0;
+            self.wy_triggered = false;
+            self.stat_line = false;
+            if self.fifo.is_some() {
+                self.fifo = Some(Fifo::new());
+            }
+        } else if !self.lcd_enable && enable {
+            // restart from dot 0 of line 0
+            self.lcd_enable = true;
+            self.first_line = true;
+            self.skip_frame = true;
+            self.update_ly(0);
+            self.update_stat();
         }
     }
 
@@ -321,7 +445,7 @@ impl Ppu {
         (if self.mode2oam_int     { 1 << 5 } else { 0 }) |
         (if self.mode1vblank_int  { 1 << 4 } else { 0 }) |
         (if self.mode0hblank_int  { 1 << 3 } else { 0 }) |
-        (if self.line == self.lyc { 1 << 2 } else { 0 }) |
+        (if self.coincidence      { 1 << 2 } else { 0 }) |
         match self.mode {
             Mode::Oam    => 0b10,
             Mode::Vram   => 0b11,
@@ -331,10 +455,19 @@ impl Ppu {
     }
 
     pub fn write_lcd_stat(&mut self, value: u8) {
+        // on DMG every source is briefly enabled while STAT is written,
+        // which fires during HBlank, VBlank or an LY match
+        if self.lcd_enable && !self.stat_line
+            && self.stat_conditions(true, false, true, true) {
+            self.stat_interrupt = true;
+        }
         self.coincidence_int = value & (1 << 6) != 0;
         self.mode2oam_int    = value & (1 << 5) != 0;
         self.mode1vblank_int = value & (1 << 4) != 0;
         self.mode0hblank_int = value & (1 << 3) != 0;
+        if self.lcd_enable {
+            self.update_stat();
+        }
     }
 
     pub fn write_bg_palette(&mut self, value: u8) {
@@ -349,18 +482,62 @@ impl Ppu {
         self.obp1.set(value);
     }
 
+    // BGP, OBP0 and OBP1, which the bus doesn't read back
+    pub fn palettes(&self) -> [u8; 3] {
+        [self.bgp.value(), self.obp0.value(), self.obp1.value()]
+    }
+
+    fn sprite_height(&self) -> u8 {
+        match self.obj_size {
+            SpriteSize::Normal => 8,
+            SpriteSize::DblHeight => 16
+        }
+    }
+
+    // first 10 sprites in OAM order that overlap the current line,
+    // regardless of their X position
+    fn oam_scan(&mut self) {
+        let size = self.sprite_height();
+        let line = self.line;
+        self.line_sprites.clear();
+        for index in 0..40 {
+            if self.line_sprites.len() == 10 {
+                break;
+            }
+            let sprite = self.sprite(index);
+            if line.wrapping_sub(sprite.y) < size {
+                self.line_sprites.push((index, sprite));
+            }
+        }
+    }
+
+    // tile data for the first 256 tiles of the background map, as used
+    // by SGB VRAM transfers
+    pub fn vram_transfer_data(&self) -> Box<[u8]> {
+        let tile_map = map_slice(&self.vram, &self.bg_tilemap_select);
+        let mut data = vec![0; 0x1000];
+        for i in 0..256 {
+            let raw_tile_num = tile_map[(i / 20) * 32 + i % 20];
+            let tile_num = tile_index(&self.bg_win_tileset_select, raw_tile_num);
+            data[i * 16..i * 16 + 16]
+                .copy_from_slice(&self.vram[tile_num * 16..tile_num * 16 + 16]);
+        }
+        data.into_boxed_slice()
+    }
+
     fn draw_line(&mut self) {
+        self.refresh_tiles();
+        let sprite_height = self.sprite_height();
+        let tiles = &self.tiles;
+        let vram = &self.vram;
         let slice_start = (self.line as usize) * SCREEN_WIDTH;
         let slice_end = slice_start + SCREEN_WIDTH;
         let pixels = &mut self.fb[slice_start .. slice_end];
+        let layers = &mut self.fb_layers[slice_start .. slice_end];
         let mut bg_priority = [false; SCREEN_WIDTH];
 
         if self.bg_display {
-            let tile_map = if self.bg_tilemap_select == Tilemap::Map1 {
-                &self.tile_map1
-            } else {
-                &self.tile_map0
-            };
+            let tile_map = map_slice(vram, &self.bg_tilemap_select);
 
             let y = self.line.wrapping_add(self.scy);
             let row = (y / 8) as usize;
@@ -368,94 +545,61 @@ impl Ppu {
                 let x = (i as u8).wrapping_add(self.scx);
                 let col = (x / 8) as usize;
                 let raw_tile_num = tile_map[row * 32 + col];
-
                 let tile_num =
-                    if self.bg_win_tileset_select == Tileset::Set0 {
-                        128 + ((raw_tile_num as i8 as i16) + 128) as usize
-                    } else {
-                        raw_tile_num as usize
-                    };
-                let tile = &self.tileset[tile_num];
-
-                let line = (y % 8) * 2;
-                let data1 = tile.data[(line as u16) as usize];
-                let data2 = tile.data[(line as u16 + 1) as usize];
-
-                let bit = (x % 8).wrapping_sub(7).wrapping_mul(0xFF) as usize;
-                let color_value = ((data2 >> bit) << 1) & 2
-                    | ((data1 >> bit) & 1);
+                    tile_index(&self.bg_win_tileset_select, raw_tile_num);
+                let color_value =
+                    tiles[tile_num].pixels[(y % 8) as usize][(x % 8) as usize];
                 let raw_color = Color::from_u8(color_value);
                 let color = self.bgp.get(&raw_color);
                 bg_priority[i] = raw_color != Color::Off;
                 pixels[i] = color;
+                layers[i] = Layer::Bg;
             }
         }
-        if self.win_display && self.wy <= self.line {
-            let tile_map = if self.win_tilemap_select == Tilemap::Map1 {
-                &self.tile_map1
-            } else {
-                &self.tile_map0
-            };
+        if self.line == self.wy {
+            self.wy_triggered = true;
+        }
+        if self.win_display && self.wy_triggered && self.wx <= WX_MAX {
+            let tile_map = map_slice(vram, &self.win_tilemap_select);
 
-            let window_x = self.wx.wrapping_sub(7);
-            let y = self.line - self.wy;
+            // WX below 7 pushes the left edge of the window off screen
+            let window_x = (self.wx as usize).saturating_sub(7);
+            let skip = 7usize.saturating_sub(self.wx as usize);
+            let y = self.window_line;
             let row = (y / 8) as usize;
-            for i in (window_x as usize)..SCREEN_WIDTH {
-                let mut x = (i as u8).wrapping_add(self.scx);
-                if x >= window_x {
-                    x = i as u8 - window_x;
-                }
+            for i in window_x..SCREEN_WIDTH {
+                let x = (i - window_x + skip) as u8;
                 let col = (x / 8) as usize;
                 let raw_tile_num = tile_map[row * 32 + col];
-
                 let tile_num =
-                    if raw_tile_num < 128
-                    && self.bg_win_tileset_select == Tileset::Set0 {
-                        256 + raw_tile_num as usize
-                    } else {
-                        raw_tile_num as usize
-                    };
-                let tile = &self.tileset[tile_num];
-
-                let line = (y % 8) * 2;
-                let data1 = tile.data[line as usize];
-                let data2 = tile.data[line as usize + 1];
-
-                let bit = (x % 8).wrapping_sub(7).wrapping_mul(0xFF) as usize;
-                let color_value = ((data2 >> bit) << 1) & 2
-                    | ((data1 >> bit) & 1);
+                    tile_index(&self.bg_win_tileset_select, raw_tile_num);
+                let color_value =
+                    tiles[tile_num].pixels[(y % 8) as usize][(x % 8) as usize];
                 let raw_color = Color::from_u8(color_value);
                 let color = self.bgp.get(&raw_color);
                 bg_priority[i] = raw_color != Color::Off;
                 pixels[i] = color;
+                layers[i] = Layer::Bg;
             }
+            // only lines the window was drawn on advance its line counter
+            self.window_line = self.window_line.wrapping_add(1);
         }
         if self.obj_display {
-            let size = match self.obj_size {
-                SpriteSize::Normal => 8,
-                SpriteSize::DblHeight => 16
-            };
-
+            let size = sprite_height;
             let current_line = self.line;
 
-            let mut sprites_to_draw: Vec<(usize, &Sprite)> = self.oam.iter()
-                .filter(|sprite| current_line.wrapping_sub(sprite.y) < size)
-                .take(10)
-                .enumerate()
-                .collect();
-
-            sprites_to_draw.sort_by(|&(a_index, a), &(b_index, b)| {
-                match a.x.cmp(&b.x) {
-                    // if X coords are same, use oam index as priority
-                    Ordering::Equal => a_index.cmp(&b_index).reverse(),
-                    // use X coord as priority
-                    other => other.reverse()
-                }
+            // lower X wins, then lower OAM index
+            let mut sprites_to_draw = self.line_sprites.clone();
+            sprites_to_draw.sort_by_key(|&(index, sprite)| {
+                (sprite.x.wrapping_add(8), index)
             });
 
+            let mut obj_drawn = [false; SCREEN_WIDTH];
             for (_, sprite) in sprites_to_draw {
-
                 let mut tile_num = sprite.tile as usize;
+                if size == 16 {
+                    tile_num &= !1;
+                }
 
                 let palette = if sprite.palette {
                     &self.obp1
@@ -471,29 +615,26 @@ impl Ppu {
                     tile_num += 1;
                     line -= 8;
                 }
-                line = line.wrapping_mul(2);
-                let tile = &self.tileset[tile_num];
-                let data1 = tile.data[line as usize];
-                let data2 = tile.data[line as usize + 1];
-
-                for x in (0..8).rev() {
-                    let bit =
-                        if sprite.x_flip {
-                            7 - x
-                        } else {
-                            x
-                        } as usize;
-                    let color_value = (((data2 >> bit) & 1) << 1)
-                        | ((data1 >> bit) & 1);
-                    let raw_color = Color::from_u8(color_value);
+                let row = &tiles[tile_num].pixels[line as usize];
+
+                for x in 0..8 {
+                    let col = if sprite.x_flip { 7 - x } else { x };
+                    let raw_color = Color::from_u8(row[col]);
                     let color = palette.get(&raw_color);
-                    let target_x = sprite.x.wrapping_add(7 - x);
-                    if target_x < SCREEN_WIDTH as u8
-                        && raw_color != Color::Off
-                    {
-                        if !sprite.bg_prio || !bg_priority[target_x as usize] {
-                            pixels[target_x as usize] = color;
-                        }
+                    let target_x = sprite.x.wrapping_add(x as u8) as usize;
+                    if target_x >= SCREEN_WIDTH || raw_color == Color::Off
+                        || obj_drawn[target_x] {
+                        continue;
+                    }
+                    // a hidden sprite pixel still masks lower priority sprites
+                    obj_drawn[target_x] = true;
+                    if !sprite.bg_prio || !bg_priority[target_x] {
+                        pixels[target_x] = color;
+                        layers[target_x] = if sprite.palette {
+                            Layer::Obj1
+                        } else {
+                            Layer::Obj0
+                        };
                     }
                 }
             }
@@ -501,6 +642,29 @@ impl Ppu {
     }
 }
 
+fn map_slice<'a>(vram: &'a [u8], tile_map: &Tilemap) -> &'a [u8] {
+    match *tile_map {
+        Tilemap::Map0 => &vram[TILE_MAP0..TILE_MAP0 + 0x400],
+        Tilemap::Map1 => &vram[TILE_MAP1..TILE_MAP1 + 0x400]
+    }
+}
+
+// index into the tileset for a BG/window tile map entry
+fn tile_index(tileset: &Tileset, raw_tile_num: u8) -> usize {
+    match *tileset {
+        Tileset::Set1 => raw_tile_num as usize,
+        Tileset::Set0 => (256 + (raw_tile_num as i8 as i16)) as usize
+    }
+}
+
+// which palette a framebuffer pixel was drawn with
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Layer {
+    Bg,
+    Obj0,
+    Obj1
+}
+
 #[derive(Debug,PartialEq)]
 enum Mode {
     Oam,    // 2
@@ -554,6 +718,11 @@ impl Palette {
         }
     }
 
+    fn value(&self) -> u8 {
+        (self.off as u8) | (self.light as u8) << 2 | (self.dark as u8) << 4
+            | (self.on as u8) << 6
+    }
+
     fn set(&mut self, value: u8) {
         self.off = Color::from_u8((value >> 0) & 0b11);
         self.light = Color::from_u8((value >> 2) & 0b11);
@@ -576,28 +745,42 @@ struct Sprite {
 }
 
 impl Sprite {
-    fn new() -> Sprite {
+    fn decode(data: &[u8]) -> Sprite {
         Sprite {
-            y: 0,
-            x: 0,
-            tile: 0,
-            bg_prio: false,
-            y_flip: false,
-            x_flip: false,
-            palette: false,
+            y: data[0].wrapping_sub(16),
+            x: data[1].wrapping_sub(8),
+            tile: data[2],
+            bg_prio: data[3] & (1 << 7) != 0,
+            y_flip: data[3] & (1 << 6) != 0,
+            x_flip: data[3] & (1 << 5) != 0,
+            palette: data[3] & (1 << 4) != 0,
         }
     }
 }
 
+// color indices, [row][column]
 #[derive(Clone, Copy)]
 struct Tile {
-    data: [u8; 16]
+    pixels: [[u8; 8]; 8]
 }
 
 impl Tile {
     fn new() -> Tile {
         Tile {
-            data: [0; 16]
+            pixels: [[0; 8]; 8]
+        }
+    }
+
+    fn decode(data: &[u8]) -> Tile {
+        let mut tile = Tile::new();
+        for row in 0..8 {
+            let lo = data[row * 2];
+            let hi = data[row * 2 + 1];
+            for col in 0..8 {
+                let bit = 7 - col;
+                tile.pixels[row][col] = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+            }
         }
+        tile
     }
 }
@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+
+use super::{Ppu, Mode, Sprite, Layer, SCREEN_WIDTH, WX_MAX};
+use super::{map_slice, tile_index};
+use Color;
+
+const OAM_SCAN_DOTS: usize = 80;
+const LINE_DOTS: usize = 456;
+const VBLANK_START: u8 = 144;
+const LAST_LINE: u8 = 153;
+
+// Dot-based renderer: the background/window fetcher and sprite fetches
+// run alongside the pixel FIFOs, so register writes take effect mid-line
+// and mode 3 stretches with SCX, the window and sprites on the line.
+pub struct Fifo {
+    dot: usize,
+    x: usize,
+    discard: usize,
+    bg: VecDeque<u8>,
+    obj: VecDeque<ObjPixel>,
+    fetcher: Fetcher,
+    window: bool,
+    sprites: Vec<(usize, Sprite)>,
+    sprite_stall: usize,
+}
+
+#[derive(Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    palette: bool,
+    bg_prio: bool,
+}
+
+struct Fetcher {
+    step: usize,
+    delay: usize,
+    tile_x: u8,
+    tile: usize,
+    row: [u8; 8],
+}
+
+impl Fetcher {
+    fn new() -> Fetcher {
+        Fetcher {
+            step: 0,
+            // the first tile fetch of a line is thrown away
+            delay: 6,
+            tile_x: 0,
+            tile: 0,
+            row: [0; 8],
+        }
+    }
+}
+
+impl Fifo {
+    pub fn new() -> Fifo {
+        Fifo {
+            dot: 0,
+            x: 0,
+            discard: 0,
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(),
+            window: false,
+            sprites: Vec::with_capacity(10),
+            sprite_stall: 0,
+        }
+    }
+
+    pub fn line_dot(&self) -> usize {
+        self.dot
+    }
+
+    pub fn dot(&mut self, ppu: &mut Ppu) {
+        if ppu.line < VBLANK_START {
+            match self.dot {
+                0 => if !ppu.first_line {
+                    ppu.mode = Mode::Oam;
+                },
+                OAM_SCAN_DOTS => {
+                    ppu.first_line = false;
+                    self.start_line(ppu);
+                    ppu.mode = Mode::Vram;
+                },
+                _ => {}
+            }
+            if ppu.mode == Mode::Vram {
+                self.mode3_dot(ppu);
+            }
+        }
+
+        self.dot += 1;
+        if self.dot == LINE_DOTS {
+            self.dot = 0;
+            ppu.line += 1;
+            if ppu.line == VBLANK_START {
+                ppu.window_line = 0;
+                ppu.wy_triggered = false;
+                ppu.enter_vblank = true;
+                ppu.mode = Mode::Vblank;
+            } else if ppu.line > LAST_LINE {
+                ppu.skip_frame = false;
+                ppu.line = 0;
+            }
+        }
+    }
+
+    fn start_line(&mut self, ppu: &mut Ppu) {
+        ppu.refresh_tiles();
+        ppu.oam_scan();
+        self.sprites.clear();
+        self.sprites.extend_from_slice(&ppu.line_sprites);
+        if ppu.line == ppu.wy {
+            ppu.wy_triggered = true;
+        }
+        self.x = 0;
+        self.discard = (ppu.scx % 8) as usize;
+        self.bg.clear();
+        self.obj.clear();
+        self.fetcher = Fetcher::new();
+        self.window = false;
+        self.sprite_stall = 0;
+    }
+
+    fn mode3_dot(&mut self, ppu: &mut Ppu) {
+        if self.sprite_stall > 0 {
+            self.sprite_stall -= 1;
+            return;
+        }
+
+        if ppu.obj_display {
+            if let Some(pos) = self.next_sprite() {
+                let (_, sprite) = self.sprites.remove(pos);
+                self.fetch_sprite(ppu, sprite);
+                // wait for the background fetch in progress, then 6 dots
+                self.sprite_stall = 6 + 5 - self.fetcher.step.min(5) - 1;
+                return;
+            }
+        }
+
+        if !self.window && ppu.win_display && ppu.wy_triggered
+            && ppu.wx <= WX_MAX && self.x + 7 >= ppu.wx as usize {
+            self.window = true;
+            self.bg.clear();
+            self.fetcher = Fetcher::new();
+            // WX below 7 pushes the left edge of the window off screen
+            self.discard = if self.x == 0 {
+                7usize.saturating_sub(ppu.wx as usize)
+            } else {
+                0
+            };
+        }
+
+        self.fetch_dot(ppu);
+
+        if let Some(bg_color) = self.bg.pop_front() {
+            if self.discard > 0 {
+                self.discard -= 1;
+                return;
+            }
+            let obj = self.obj.pop_front();
+            self.output(ppu, bg_color, obj);
+            self.x += 1;
+            if self.x == SCREEN_WIDTH {
+                ppu.mode = Mode::Hblank;
+                if self.window {
+                    ppu.window_line = ppu.window_line.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    // lowest X wins, earlier OAM entries win ties
+    fn next_sprite(&self) -> Option<usize> {
+        let x = self.x as u8;
+        self.sprites.iter()
+            .enumerate()
+            .filter(|&(_, &(_, sprite))| sprite.x.wrapping_add(8) <= x + 8)
+            .min_by_key(|&(_, &(index, sprite))| (sprite.x.wrapping_add(8), index))
+            .map(|(pos, _)| pos)
+    }
+
+    fn fetch_dot(&mut self, ppu: &Ppu) {
+        if self.fetcher.delay > 0 {
+            self.fetcher.delay -= 1;
+            return;
+        }
+        let y = if self.window {
+            ppu.window_line
+        } else {
+            ppu.line.wrapping_add(ppu.scy)
+        };
+        match self.fetcher.step {
+            1 => {
+                let (tile_map, col) = if self.window {
+                    (&ppu.win_tilemap_select, self.fetcher.tile_x)
+                } else {
+                    (&ppu.bg_tilemap_select,
+                     (ppu.scx / 8).wrapping_add(self.fetcher.tile_x) & 31)
+                };
+                let map = map_slice(&ppu.vram, tile_map);
+                let raw_tile_num = map[(y / 8) as usize * 32 + col as usize];
+                self.fetcher.tile =
+                    tile_index(&ppu.bg_win_tileset_select, raw_tile_num);
+            },
+            5 => {
+                self.fetcher.row = ppu.tiles[self.fetcher.tile].pixels[(y % 8) as usize];
+            },
+            6 => {
+                if !self.bg.is_empty() {
+                    return;
+                }
+                self.bg.extend(self.fetcher.row.iter());
+                self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+                self.fetcher.step = 0;
+                return;
+            },
+            _ => {}
+        }
+        self.fetcher.step += 1;
+    }
+
+    fn fetch_sprite(&mut self, ppu: &Ppu, sprite: Sprite) {
+        let size = ppu.sprite_height();
+        let mut tile_num = sprite.tile as usize;
+        if size == 16 {
+            tile_num &= !1;
+        }
+        let mut line = ppu.line.wrapping_sub(sprite.y);
+        if sprite.y_flip {
+            line = size - line - 1;
+        }
+        if line >= 8 {
+            tile_num += 1;
+            line -= 8;
+        }
+        let row = &ppu.tiles[tile_num].pixels[line as usize];
+
+        // sprites hanging off the left edge lose their first pixels
+        let skip = (8 as usize).saturating_sub(sprite.x.wrapping_add(8) as usize);
+        while self.obj.len() < 8 {
+            self.obj.push_back(ObjPixel { color: 0, palette: false, bg_prio: false });
+        }
+        for i in skip..8 {
+            let color = row[if sprite.x_flip { 7 - i } else { i }];
+            let slot = &mut self.obj[i - skip];
+            // pixels already in the FIFO belong to higher priority sprites
+            if slot.color == 0 && color != 0 {
+                *slot = ObjPixel {
+                    color: color,
+                    palette: sprite.palette,
+                    bg_prio: sprite.bg_prio,
+                };
+            }
+        }
+    }
+
+    fn output(&self, ppu: &mut Ppu, bg_color: u8, obj: Option<ObjPixel>) {
+        let bg_color = if ppu.bg_display { bg_color } else { 0 };
+        let index = ppu.line as usize * SCREEN_WIDTH + self.x;
+        let (color, layer) = match obj {
+            Some(obj) if obj.color != 0 && ppu.obj_display
+                && !(obj.bg_prio && bg_color != 0) => {
+                let raw_color = Color::from_u8(obj.color);
+                if obj.palette {
+                    (ppu.obp1.get(&raw_color), Layer::Obj1)
+                } else {
+                    (ppu.obp0.get(&raw_color), Layer::Obj0)
+                }
+            },
+            _ => (ppu.bgp.get(&Color::from_u8(bg_color)), Layer::Bg)
+        };
+        ppu.fb[index] = color;
+        ppu.fb_layers[index] = layer;
+    }
+}
@@ -0,0 +1,418 @@
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+const GB_WIDTH: usize = 160;
+const GB_HEIGHT: usize = 144;
+// position of the game screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+const ATTR_WIDTH: usize = GB_WIDTH / 8;
+const ATTR_HEIGHT: usize = GB_HEIGHT / 8;
+const PACKET_SIZE: usize = 16;
+
+use Color;
+use Rgb;
+
+pub struct Sgb {
+    // P1 packet receiver
+    receiving: bool,
+    bit_count: usize,
+    packet: [u8; PACKET_SIZE],
+    command: Vec<u8>,
+    p1_select: u8,
+
+    // MLT_REQ
+    players: u8,
+    current_player: u8,
+
+    palettes: [[Rgb; 4]; 4],
+    attributes: Box<[u8]>,
+    mask: Mask,
+    transfer: Option<Transfer>,
+
+    border_tiles: Box<[u8]>,
+    border_map: Box<[u16]>,
+    border_palettes: [[Rgb; 16]; 4],
+
+    fb: Box<[Rgb]>,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        // default SGB palette 1-A
+        let default_palette = [
+            Rgb::from_bgr555(0x67BF),
+            Rgb::from_bgr555(0x265B),
+            Rgb::from_bgr555(0x10B5),
+            Rgb::from_bgr555(0x2866),
+        ];
+        Sgb {
+            receiving: false,
+            bit_count: 0,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            p1_select: 0x30,
+
+            players: 1,
+            current_player: 0,
+
+            palettes: [default_palette; 4],
+            attributes: vec![0; ATTR_WIDTH * ATTR_HEIGHT].into_boxed_slice(),
+            mask: Mask::Cancel,
+            transfer: None,
+
+            border_tiles: vec![0; 0x2000].into_boxed_slice(),
+            border_map: vec![0; 32 * 28].into_boxed_slice(),
+            border_palettes: [[Rgb::new(0, 0, 0); 16]; 4],
+
+            fb: vec![default_palette[0]; SGB_WIDTH * SGB_HEIGHT]
+                .into_boxed_slice(),
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[Rgb] {
+        &self.fb
+    }
+
+    pub fn read_joypad(&self, buttons: u8) -> u8 {
+        if self.players > 1 && self.p1_select == 0x30 {
+            0xFF - self.current_player
+        } else {
+            buttons
+        }
+    }
+
+    pub fn write_joypad(&mut self, value: u8) {
+        let select = value & 0x30;
+        let last = self.p1_select;
+        self.p1_select = select;
+        if select == last {
+            return;
+        }
+        match select {
+            0x00 => {
+                // reset pulse starts a new packet
+                self.receiving = true;
+                self.bit_count = 0;
+                self.packet = [0; PACKET_SIZE];
+            },
+            0x10 | 0x20 if self.receiving && last == 0x30 => {
+                let bit = if select == 0x10 { 1 } else { 0 };
+                if self.bit_count < PACKET_SIZE * 8 {
+                    let byte = self.bit_count / 8;
+                    self.packet[byte] |= bit << (self.bit_count % 8);
+                    self.bit_count += 1;
+                } else {
+                    // stop bit
+                    self.receiving = false;
+                    self.receive_packet();
+                }
+            },
+            0x30 => if !self.receiving && self.players > 1 && last == 0x10 {
+                self.current_player = (self.current_player + 1) % self.players;
+            },
+            _ => {}
+        }
+    }
+
+    pub fn transfer_pending(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    // called at vblank with the 4KB of tile data currently on screen
+    pub fn vram_transfer(&mut self, data: &[u8]) {
+        match self.transfer.take() {
+            Some(Transfer::Chr(high)) => {
+                let offset = if high { 0x1000 } else { 0 };
+                self.border_tiles[offset..offset + 0x1000]
+                    .copy_from_slice(&data[..0x1000]);
+            },
+            Some(Transfer::Pct) => {
+                for i in 0..self.border_map.len() {
+                    self.border_map[i] =
+                        data[i * 2] as u16 | (data[i * 2 + 1] as u16) << 8;
+                }
+                for palette in 0..4 {
+                    for color in 0..16 {
+                        let offset = 0x800 + (palette * 16 + color) * 2;
+                        let value =
+                            data[offset] as u16 | (data[offset + 1] as u16) << 8;
+                        self.border_palettes[palette][color] =
+                            Rgb::from_bgr555(value);
+                    }
+                }
+            },
+            None => {}
+        }
+    }
+
+    pub fn render(&mut self, screen: &[Color]) {
+        self.draw_border();
+        for y in 0..GB_HEIGHT {
+            for x in 0..GB_WIDTH {
+                let color = match self.mask {
+                    Mask::Cancel => {
+                        let attr = self.attributes[(y / 8) * ATTR_WIDTH + x / 8];
+                        let shade = screen[y * GB_WIDTH + x];
+                        self.palettes[attr as usize][shade as usize]
+                    },
+                    Mask::Freeze => continue,
+                    Mask::Black => Rgb::new(0, 0, 0),
+                    Mask::Color0 => self.palettes[0][0],
+                };
+                self.fb[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X] = color;
+            }
+        }
+    }
+
+    fn draw_border(&mut self) {
+        let backdrop = self.palettes[0][0];
+        for row in 0..28 {
+            for col in 0..32 {
+                let in_screen = col >= SCREEN_X / 8 && col < (SCREEN_X + GB_WIDTH) / 8
+                    && row >= SCREEN_Y / 8 && row < (SCREEN_Y + GB_HEIGHT) / 8;
+                if in_screen {
+                    continue;
+                }
+                let entry = self.border_map[row * 32 + col];
+                let tile = (entry & 0xFF) as usize;
+                let palette = ((entry >> 10) & 0b11) as usize;
+                let x_flip = entry & (1 << 14) != 0;
+                let y_flip = entry & (1 << 15) != 0;
+                let data = &self.border_tiles[tile * 32..tile * 32 + 32];
+                for y in 0..8 {
+                    let line = if y_flip { 7 - y } else { y };
+                    for x in 0..8 {
+                        let bit = if x_flip { x } else { 7 - x };
+                        // SNES 4bpp planar tile format
+                        let color_value =
+                            (data[line * 2] >> bit) & 1
+                            | ((data[line * 2 + 1] >> bit) & 1) << 1
+                            | ((data[16 + line * 2] >> bit) & 1) << 2
+                            | ((data[16 + line * 2 + 1] >> bit) & 1) << 3;
+                        let color = if color_value == 0 {
+                            backdrop
+                        } else {
+                            self.border_palettes[palette][color_value as usize]
+                        };
+                        self.fb[(row * 8 + y) * SGB_WIDTH + col * 8 + x] = color;
+                    }
+                }
+            }
+        }
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        let length = match self.command[0] & 0b111 {
+            0 => 1,
+            n => n as usize
+        };
+        if self.command.len() >= length * PACKET_SIZE {
+            let data = ::std::mem::replace(&mut self.command, Vec::new());
+            self.execute(&data);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match Command::from_u8(data[0] >> 3) {
+            Command::Pal01 => self.set_palettes(0, 1, data),
+            Command::Pal23 => self.set_palettes(2, 3, data),
+            Command::Pal03 => self.set_palettes(0, 3, data),
+            Command::Pal12 => self.set_palettes(1, 2, data),
+            Command::AttrBlk => self.attr_blk(data),
+            Command::AttrLin => self.attr_lin(data),
+            Command::AttrDiv => self.attr_div(data),
+            Command::AttrChr => self.attr_chr(data),
+            Command::MltReq => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1
+                };
+                self.current_player = 0;
+            },
+            Command::ChrTrn => self.transfer = Some(Transfer::Chr(data[1] & 1 != 0)),
+            Command::PctTrn => self.transfer = Some(Transfer::Pct),
+            Command::MaskEn => self.mask = match data[1] & 0b11 {
+                1 => Mask::Freeze,
+                2 => Mask::Black,
+                3 => Mask::Color0,
+                _ => Mask::Cancel
+            },
+            Command::Unsupported(_) => {}
+        }
+    }
+
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |i: usize| {
+            Rgb::from_bgr555(data[1 + i * 2] as u16 | (data[2 + i * 2] as u16) << 8)
+        };
+        // color 0 is shared by all palettes
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks(6).take(count) {
+            if set.len() < 6 {
+                break;
+            }
+            let control = set[0] & 0b111;
+            let inside = set[1] & 0b11;
+            let outside = (set[1] >> 4) & 0b11;
+            // a lone inside or outside change also applies to the line
+            let line = match control {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                c if c & 0b010 != 0 => Some((set[1] >> 2) & 0b11),
+                _ => None
+            };
+            let (x1, y1) = ((set[2] & 0x1F) as usize, (set[3] & 0x1F) as usize);
+            let (x2, y2) = ((set[4] & 0x1F) as usize, (set[5] & 0x1F) as usize);
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let palette = if within
+                        && (x == x1 || x == x2 || y == y1 || y == y2) {
+                        line
+                    } else if within {
+                        if control & 0b001 != 0 { Some(inside) } else { None }
+                    } else if control & 0b100 != 0 {
+                        Some(outside)
+                    } else {
+                        None
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTR_WIDTH + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &set in data[2..].iter().take(count) {
+            let line = (set & 0x1F) as usize;
+            let palette = (set >> 5) & 0b11;
+            if set & (1 << 7) != 0 {
+                if line < ATTR_HEIGHT {
+                    for x in 0..ATTR_WIDTH {
+                        self.attributes[line * ATTR_WIDTH + x] = palette;
+                    }
+                }
+            } else if line < ATTR_WIDTH {
+                for y in 0..ATTR_HEIGHT {
+                    self.attributes[y * ATTR_WIDTH + line] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0b11;
+        let before = (data[1] >> 2) & 0b11;
+        let on_line = (data[1] >> 4) & 0b11;
+        let horizontal = data[1] & (1 << 6) != 0;
+        let split = (data[2] & 0x1F) as usize;
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let pos = if horizontal { y } else { x };
+                self.attributes[y * ATTR_WIDTH + x] = if pos < split {
+                    before
+                } else if pos == split {
+                    on_line
+                } else {
+                    after
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = (data[1] as usize) % ATTR_WIDTH;
+        let mut y = (data[2] as usize) % ATTR_HEIGHT;
+        let count = (data[3] as usize | (data[4] as usize) << 8)
+            .min(ATTR_WIDTH * ATTR_HEIGHT);
+        let vertical = data[5] & 1 != 0;
+        for i in 0..count {
+            let byte = match data.get(6 + i / 4) {
+                Some(&byte) => byte,
+                None => break
+            };
+            let palette = (byte >> (6 - (i % 4) * 2)) & 0b11;
+            self.attributes[y * ATTR_WIDTH + x] = palette;
+            if vertical {
+                y += 1;
+                if y == ATTR_HEIGHT {
+                    y = 0;
+                    x = (x + 1) % ATTR_WIDTH;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_WIDTH {
+                    x = 0;
+                    y = (y + 1) % ATTR_HEIGHT;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Pal01,   // 00
+    Pal23,   // 01
+    Pal03,   // 02
+    Pal12,   // 03
+    AttrBlk, // 04
+    AttrLin, // 05
+    AttrDiv, // 06
+    AttrChr, // 07
+    MltReq,  // 11
+    ChrTrn,  // 13
+    PctTrn,  // 14
+    MaskEn,  // 17
+    Unsupported(u8)
+}
+
+impl Command {
+    fn from_u8(value: u8) -> Command {
+        use self::Command::*;
+        match value {
+            0x00 => Pal01,
+            0x01 => Pal23,
+            0x02 => Pal03,
+            0x03 => Pal12,
+            0x04 => AttrBlk,
+            0x05 => AttrLin,
+            0x06 => AttrDiv,
+            0x07 => AttrChr,
+            0x11 => MltReq,
+            0x13 => ChrTrn,
+            0x14 => PctTrn,
+            0x17 => MaskEn,
+            _ => Unsupported(value)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Mask {
+    Cancel,
+    Freeze,
+    Black,
+    Color0
+}
+
+#[derive(Debug, PartialEq)]
+enum Transfer {
+    Chr(bool), // upper half of the tile set
+    Pct
+}
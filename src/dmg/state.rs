@@ -0,0 +1,158 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use dmg::{Cpu, Interconnect};
+use dmg::cpu::Reg16;
+
+// Save states hold what a game can see: CPU registers, memory, the MBC
+// and the readable I/O registers. Internal timing (the PPU's position in
+// a line, DIV, a running OAM DMA, APU sequencers) isn't kept, so a loaded
+// state resumes close to, not exactly at, where it was saved.
+
+const MAGIC: &'static [u8] = b"RBST";
+const VERSION: u8 = 2;
+
+// title through global checksum, so a state isn't loaded into another game
+const HEADER_START: usize = 0x134;
+const HEADER_END: usize = 0x150;
+
+const REGS: [Reg16; 6] = [Reg16::AF, Reg16::BC, Reg16::DE, Reg16::HL, Reg16::SP, Reg16::PC];
+
+// VRAM, WRAM, OAM, HRAM
+const MEMORY: [(u16, u16); 4] = [
+    (0x8000, 0xA000),
+    (0xC000, 0xE000),
+    (0xFE00, 0xFEA0),
+    (0xFF80, 0xFFFF),
+];
+
+// Written back in this order, NR52 first so the APU takes the rest.
+// Registers whose writes do more than store a value are left out: P1,
+// serial, DIV, LY and DMA. The frequency low bytes are write-only.
+const IO_REGS: [u16; 29] = [
+    0xFF26,
+    0xFF10, 0xFF11, 0xFF12, 0xFF14,
+    0xFF16, 0xFF17, 0xFF19,
+    0xFF1A, 0xFF1B, 0xFF1C, 0xFF1E,
+    0xFF20, 0xFF21, 0xFF22, 0xFF23,
+    0xFF24, 0xFF25,
+    0xFF05, 0xFF06, 0xFF07, 0xFF0F,
+    0xFF40, 0xFF41, 0xFF42, 0xFF43, 0xFF45, 0xFF4A, 0xFF4B,
+];
+// NRx4: bit 7 restarts the channel
+const APU_TRIGGERS: [u16; 4] = [0xFF14, 0xFF19, 0xFF1E, 0xFF23];
+const WAVE_RAM: (u16, u16) = (0xFF30, 0xFF40);
+
+pub fn save(cpu: &Cpu, interconnect: &Interconnect) -> Vec<u8> {
+    let cart = interconnect.cart();
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend_from_slice(&cart.rom[HEADER_START..HEADER_END]);
+
+    for &reg in REGS.iter() {
+        out.write_u16::<LittleEndian>(cpu.read_reg16(reg)).unwrap();
+    }
+    out.extend_from_slice(&[cpu.ime as u8, cpu.halted as u8,
+                            cpu.halt_no_jump as u8, cpu.stopped as u8,
+                            cpu.locked as u8, cpu.ime_next_cycle as u8]);
+
+    out.extend_from_slice(&cart.mbc_state());
+    out.write_u32::<LittleEndian>(cart.ram.len() as u32).unwrap();
+    out.extend_from_slice(&cart.ram);
+
+    for &(start, end) in MEMORY.iter() {
+        out.extend((start..end).map(|addr| interconnect.peek(addr)));
+    }
+    out.extend(IO_REGS.iter().map(|&addr| interconnect.peek(addr)));
+    out.extend((WAVE_RAM.0..WAVE_RAM.1).map(|addr| interconnect.peek(addr)));
+    out.extend_from_slice(&interconnect.palettes());
+    out.push(interconnect.peek(0xFF70));
+    out.push(interconnect.peek(0xFFFF));
+    out
+}
+
+// Nothing is changed unless the whole state could be read
+pub fn load(cpu: &mut Cpu, interconnect: &mut Interconnect, data: &[u8]) -> Result<(), String> {
+    let mut data = data;
+    if take(&mut data, MAGIC.len()).ok() != Some(MAGIC) {
+        return Err("not a save state".into());
+    }
+    let version = take(&mut data, 1)?[0];
+    if version != VERSION {
+        return Err(format!("unsupported save state version {}", version));
+    }
+    if take(&mut data, HEADER_END - HEADER_START)?
+        != &interconnect.cart().rom[HEADER_START..HEADER_END] {
+        return Err("save state is for a different ROM".into());
+    }
+
+    let mut regs = [0; 6];
+    for reg in regs.iter_mut() {
+        *reg = data.read_u16::<LittleEndian>().map_err(|_| truncated())?;
+    }
+    let flags = take(&mut data, 6)?;
+
+    let mut mbc = [0; 5];
+    mbc.copy_from_slice(take(&mut data, 5)?);
+    let ram_len = data.read_u32::<LittleEndian>().map_err(|_| truncated())? as usize;
+    if ram_len != interconnect.cart().ram.len() {
+        return Err(format!("save state has {} bytes of cartridge RAM, expected {}",
+                           ram_len, interconnect.cart().ram.len()));
+    }
+    let ram = take(&mut data, ram_len)?;
+
+    let mut memory = Vec::new();
+    for &(start, end) in MEMORY.iter() {
+        memory.push((start, take(&mut data, (end - start) as usize)?));
+    }
+    let io = take(&mut data, IO_REGS.len())?;
+    let wave = take(&mut data, (WAVE_RAM.1 - WAVE_RAM.0) as usize)?;
+    let palettes = take(&mut data, 3)?;
+    let extra = take(&mut data, 2)?;
+    if !data.is_empty() {
+        return Err("save state has trailing data".into());
+    }
+
+    interconnect.cart_mut().set_mbc_state(mbc);
+    interconnect.cart_mut().ram.copy_from_slice(ram);
+    for (start, bytes) in memory {
+        for (i, &byte) in bytes.iter().enumerate() {
+            interconnect.poke(start + i as u16, byte);
+        }
+    }
+    for (&addr, &value) in IO_REGS.iter().zip(io) {
+        let value = if APU_TRIGGERS.contains(&addr) { value & 0x7F } else { value };
+        interconnect.poke(addr, value);
+    }
+    for (i, &value) in wave.iter().enumerate() {
+        interconnect.poke(WAVE_RAM.0 + i as u16, value);
+    }
+    for (i, &value) in palettes.iter().enumerate() {
+        interconnect.poke(0xFF47 + i as u16, value);
+    }
+    interconnect.poke(0xFF70, extra[0]);
+    interconnect.poke(0xFFFF, extra[1]);
+
+    for (&reg, &value) in REGS.iter().zip(regs.iter()) {
+        cpu.write_reg16(reg, value);
+    }
+    cpu.ime = flags[0] != 0;
+    cpu.halted = flags[1] != 0;
+    cpu.halt_no_jump = flags[2] != 0;
+    cpu.stopped = flags[3] != 0;
+    cpu.locked = flags[4] != 0;
+    cpu.ime_next_cycle = flags[5] != 0;
+    Ok(())
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if data.len() < len {
+        return Err(truncated());
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn truncated() -> String {
+    "save state is truncated".into()
+}
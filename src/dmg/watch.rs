@@ -0,0 +1,107 @@
+use std::cell::Cell;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Cpu,
+    Dma,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub access: Access,
+    // only trigger when this value is read or written
+    pub value: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHit {
+    pub id: usize,
+    pub addr: u16,
+    pub access: Access,
+    pub old: u8,
+    pub new: u8,
+    pub source: Source,
+}
+
+// Interconnect only holds one of these while watchpoints are set, so the
+// bus pays for a single check otherwise. Reads go through &self, hence the
+// Cell for recording hits.
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    hit: Cell<Option<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            list: Vec::new(),
+            hit: Cell::new(None),
+        }
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.list.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.list.len();
+        self.list.retain(|w| w.id != id);
+        self.list.len() != count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn watching(&self, addr: u16, access: Access) -> bool {
+        self.list.iter().any(|w| {
+            w.access.covers(access) && addr >= w.start && addr <= w.end
+        })
+    }
+
+    // the first hit is kept until it's taken
+    pub fn check(&self, addr: u16, access: Access, old: u8, new: u8,
+                 source: Source) {
+        if self.hit.get().is_some() {
+            return;
+        }
+        let found = self.list.iter().find(|w| {
+            w.access.covers(access) && addr >= w.start && addr <= w.end
+                && w.value.map_or(true, |value| value == new)
+        });
+        if let Some(watchpoint) = found {
+            self.hit.set(Some(WatchHit {
+                id: watchpoint.id,
+                addr: addr,
+                access: access,
+                old: old,
+                new: new,
+                source: source,
+            }));
+        }
+    }
+
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.replace(None)
+    }
+}
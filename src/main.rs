@@ -2,16 +2,19 @@ extern crate byteorder;
 extern crate sdl2;
 #[macro_use]
 extern crate nom;
+extern crate libc;
 
 #[macro_use]
 mod events;
 mod dmg;
 mod debugger;
+mod palette;
 
 use std::fs;
 use std::env;
 use std::io::Read;
 use std::path::Path;
+use std::process;
 use sdl2::pixels::PixelFormatEnum;
 use std::thread::sleep;
 use std::time;
@@ -20,6 +23,8 @@ use std::sync::mpsc::channel;
 struct_events!{
     keyboard: {
         key_escape: Escape,
+        key_palette: P,
+        key_debug: F12,
         key_up: Up,
         key_down: Down
     },
@@ -29,12 +34,162 @@ struct_events!{
 }
 
 fn main() {
+    // rustboy trace-diff <reference> <boot> <rom> [--stub-ly]
+    if env::args().nth(1).map_or(false, |arg| arg == "trace-diff") {
+        trace_diff(env::args().skip(2).collect());
+        return;
+    }
+
+    let mut files = Vec::new();
+    let mut palette_file = None;
+    let mut color_mode = None;
+    let mut fifo = false;
+    let mut disasm_bank = None;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut dap = None;
+    let mut trace_file = None;
+    let mut state_file = None;
+    let mut strict = false;
+    let mut trace_options = debugger::trace::TraceOptions::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--palettes" => palette_file = args.next(),
+            "--color" => color_mode = args.next(),
+            "--fifo" => fifo = true,
+            "--disasm" => disasm_bank = args.next(),
+            "--debug" => debug = true,
+            "--gdb" => gdb_port = args.next(),
+            "--dap" => dap = args.next(),
+            "--trace" => trace_file = args.next(),
+            "--load-state" => state_file = args.next(),
+            "--strict" => strict = true,
+            "--trace-pc" => match args.next().and_then(|r| debugger::trace::parse_range(&r)) {
+                Some(range) => trace_options.range = Some(range),
+                None => println!("--trace-pc expects START-END in hex"),
+            },
+            "--trace-bank" => match args.next().and_then(|b| usize::from_str_radix(&b, 16).ok()) {
+                Some(bank) => trace_options.bank = Some(bank),
+                None => println!("--trace-bank expects a bank in hex"),
+            },
+            "--trace-ly" => trace_options.ly = true,
+            "--trace-cycles" => trace_options.cycles = true,
+            _ => files.push(arg)
+        }
+    }
+
+    // rustboy --disasm <bank> <rom>: print a bank's disassembly and exit
+    if let Some(bank) = disasm_bank {
+        let rom = read_bin(files.last().expect("no ROM given"));
+        let result = usize::from_str_radix(&bank, 16)
+            .map_err(|_| format!("invalid bank: {}", bank))
+            .and_then(|bank| debugger::disasm::print_bank(&rom, bank));
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+        return;
+    }
+
+    // --dap stdio: the protocol gets stdout, everything else goes to stderr
+    let dap_stdio = match dap {
+        Some(ref transport) if transport == "stdio" => {
+            match debugger::dap::take_stdout() {
+                Ok(protocol) => Some(protocol),
+                Err(e) => {
+                    println!("Unable to serve DAP over stdio: {}", e);
+                    return;
+                }
+            }
+        },
+        _ => None
+    };
+
+    let boot = read_bin(&files[0]);
+    let rom = read_bin(&files[1]);
+
+    let mut dmg = dmg::Dmg::new(boot, rom);
+    dmg.set_fifo_renderer(fifo);
+    // --strict: break into the debugger on illegal opcodes and bad register
+    // accesses instead of carrying on as the hardware would
+    dmg.set_strict(strict);
+    // --load-state <file>: resume from a save state, e.g. one from a crash report
+    if let Some(path) = state_file {
+        let result = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| dmg.load_state(&data));
+        if let Err(e) = result {
+            println!("Unable to load state from {}: {}", path, e);
+        }
+    }
+    let (width, height) = dmg.screen_size();
+
+    let mut palettes = palette::presets();
+    if let Some(path) = palette_file {
+        match palette::load(&path) {
+            Ok(user_palettes) => palettes.extend(user_palettes),
+            Err(e) => println!("Unable to load palettes from {}: {}", path, e),
+        }
+    }
+    match color_mode.as_ref().map(|mode| mode.as_str()) {
+        Some("auto") => {
+            let cart = dmg.cart();
+            palettes.insert(0, palette::cgb_automatic(cart.title_checksum(),
+                                                      cart.title()));
+        },
+        Some(combo) => match palette::cgb_manual(combo) {
+            Some(palette) => palettes.insert(0, palette),
+            None => println!("Unknown color palette: {}", combo),
+        },
+        None => {}
+    }
+    let mut palette_index = 0;
+
+    // --debug starts paused at the prompt, F12 breaks in while running
+    let symbols = debugger::symbols::Symbols::load_for_rom(&files[1])
+        .map(|(path, symbols)| {
+            println!("Loaded {} symbols from {}", symbols.len(), path.display());
+            symbols
+        });
+    let mut debugger = match dap_stdio {
+        Some(protocol) => {
+            let mut debugger = debugger::Debugger::without_repl(dmg, symbols);
+            debugger.serve_dap_stdio(protocol);
+            debugger
+        },
+        None => debugger::Debugger::new(dmg, symbols, debug),
+    };
+    // --gdb <port>: serve the GDB remote protocol on localhost
+    if let Some(port) = gdb_port {
+        let result = port.parse()
+            .map_err(|_| format!("invalid port: {}", port))
+            .and_then(|port| debugger.listen_gdb(port).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            println!("Unable to start GDB server: {}", e);
+        }
+    }
+    // --trace <file>: log every instruction in the gameboy-doctor format
+    if let Some(path) = trace_file {
+        if let Err(e) = debugger.start_trace(&path, trace_options) {
+            println!("Unable to trace to {}: {}", path, e);
+        }
+    }
+    // --dap <port>: serve the Debug Adapter Protocol on localhost
+    if let Some(port) = dap.as_ref().filter(|transport| *transport != "stdio") {
+        let result = port.parse()
+            .map_err(|_| format!("invalid port: {}", port))
+            .and_then(|port| debugger.listen_dap(port).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            println!("Unable to start DAP server: {}", e);
+        }
+    }
+
     // Init SDL2
     let sdl_context = sdl2::init().unwrap();
     let video = sdl_context.video().unwrap();
 
     // Create window
-    let window = video.window("Rustboy", 640, 576)
+    let window = video.window("Rustboy", width as u32 * 4, height as u32 * 4)
         .position_centered().opengl()
         .build().unwrap();
 
@@ -42,11 +197,11 @@ fn main() {
         .accelerated()
         .build().unwrap();
     let mut texture = renderer.create_texture_streaming(
-        PixelFormatEnum::RGB24, 160, 144).unwrap();
+        PixelFormatEnum::RGB24, width as u32, height as u32).unwrap();
 
     texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-        for y in 0..144 {
-            for x in 0..160 {
+        for y in 0..height {
+            for x in 0..width {
                 let offset = y*pitch + x*3;
                 buffer[offset + 0] = x as u8;
                 buffer[offset + 1] = y as u8;
@@ -55,14 +210,6 @@ fn main() {
         }
     }).unwrap();
 
-    let boot_file_name = env::args().nth(1).unwrap();
-    let rom_file_name = env::args().nth(2).unwrap();
-
-    let boot = read_bin(boot_file_name);
-    let rom = read_bin(rom_file_name);
-
-    let mut dmg = dmg::Dmg::new(boot, rom);
-
     let mut events = Events::new(sdl_context.event_pump().unwrap());
 
     let mut cycles = 0;
@@ -74,18 +221,51 @@ fn main() {
             break;
         }
 
-        while cycles < 0x4444 {
-            cycles += dmg.step();
+        if events.now.key_debug == Some(true) {
+            debugger.interrupt();
+        }
+        debugger.poll_input();
+        if debugger.quit() {
+            break;
+        }
+
+        if events.now.key_palette == Some(true) {
+            palette_index = (palette_index + 1) % palettes.len();
+            println!("Palette: {}", palettes[palette_index].name);
         }
 
-        cycles -= 0x4444;
+        while cycles < 0x4444 && debugger.running() {
+            cycles += debugger.run_step();
+        }
+
+        // a paused frame picks up where it left off on continue
+        if cycles >= 0x4444 {
+            cycles -= 0x4444;
+        }
 
-        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for i in 0..(160 * 144) {
-                let offset = i * 3;
-                buffer[offset] = dmg.framebuffer()[i].red();
-                buffer[offset + 1] = dmg.framebuffer()[i].green();
-                buffer[offset + 2] = dmg.framebuffer()[i].blue();
+        let dmg = debugger.dmg();
+        let lcd_visible = dmg.lcd_visible();
+        texture.with_lock(None, |buffer: &mut [u8], _: usize| {
+            match dmg.sgb_framebuffer() {
+                Some(fb) => for i in 0..(width * height) {
+                    let offset = i * 3;
+                    buffer[offset] = fb[i].red;
+                    buffer[offset + 1] = fb[i].green;
+                    buffer[offset + 2] = fb[i].blue;
+                },
+                None => for i in 0..(width * height) {
+                    let offset = i * 3;
+                    let palette = &palettes[palette_index];
+                    let color = if lcd_visible {
+                        palette.get(dmg.framebuffer_layers()[i], dmg.framebuffer()[i])
+                    } else {
+                        // an LCD that's switched off shows its lightest shade
+                        palette.bg[0]
+                    };
+                    buffer[offset] = color.red;
+                    buffer[offset + 1] = color.green;
+                    buffer[offset + 2] = color.blue;
+                }
             }
         }).unwrap();
 
@@ -96,6 +276,28 @@ fn main() {
     }
 }
 
+// Headless: runs until the first line that differs from the reference
+// trace, exiting with status 1 if one does
+fn trace_diff(args: Vec<String>) {
+    let stub_ly = args.iter().any(|arg| arg == "--stub-ly");
+    let files: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if files.len() != 3 {
+        println!("usage: rustboy trace-diff <reference> <boot> <rom> [--stub-ly]");
+        process::exit(2);
+    }
+
+    let mut dmg = dmg::Dmg::new(read_bin(files[1]), read_bin(files[2]));
+    dmg.set_ly_stub(stub_ly);
+    match debugger::trace_diff::run(dmg, files[0]) {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+    }
+}
+
 fn read_bin<P: AsRef<Path>>(path: P) -> Box<[u8]> {
     let mut file = fs::File::open(path).unwrap();
     let mut file_buf = Vec::new();
@@ -121,31 +323,31 @@ impl Color {
             _ => Off
         }
     }
+}
 
-    pub fn red(&self) -> u8 {
-        match *self {
-            Color::Off => 156,
-            Color::Light => 140,
-            Color::Dark => 48,
-            Color::On => 15
-        }
-    }
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8
+}
 
-    pub fn green(&self) -> u8 {
-        match *self {
-            Color::Off => 189,
-            Color::Light => 173,
-            Color::Dark => 98,
-            Color::On => 56
+impl Rgb {
+    pub fn new(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb {
+            red: red,
+            green: green,
+            blue: blue
         }
     }
 
-    pub fn blue(&self) -> u8 {
-        match *self {
-            Color::Off => 15,
-            Color::Light => 15,
-            Color::Dark => 48,
-            Color::On => 15
+    // 15-bit BGR as used by the SGB and CGB palettes
+    pub fn from_bgr555(value: u16) -> Rgb {
+        let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+        Rgb {
+            red: expand(value & 0x1F),
+            green: expand((value >> 5) & 0x1F),
+            blue: expand((value >> 10) & 0x1F)
         }
     }
- }
+}
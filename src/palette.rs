@@ -0,0 +1,329 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use dmg::ppu::Layer;
+use Color;
+use Rgb;
+
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub name: String,
+    pub bg: [Rgb; 4],
+    pub obj0: [Rgb; 4],
+    pub obj1: [Rgb; 4],
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: [Rgb; 4]) -> Palette {
+        Palette {
+            name: name.into(),
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        }
+    }
+
+    pub fn with_layers(name: &str, bg: [Rgb; 4], obj0: [Rgb; 4], obj1: [Rgb; 4])
+                       -> Palette {
+        Palette {
+            name: name.into(),
+            bg: bg,
+            obj0: obj0,
+            obj1: obj1,
+        }
+    }
+
+    pub fn get(&self, layer: Layer, color: Color) -> Rgb {
+        let colors = match layer {
+            Layer::Bg => &self.bg,
+            Layer::Obj0 => &self.obj0,
+            Layer::Obj1 => &self.obj1,
+        };
+        colors[color as usize]
+    }
+}
+
+pub fn presets() -> Vec<Palette> {
+    vec![
+        Palette::new("DMG green", [
+            Rgb::new(156, 189, 15),
+            Rgb::new(140, 173, 15),
+            Rgb::new(48, 98, 48),
+            Rgb::new(15, 56, 15),
+        ]),
+        Palette::new("Pocket grey", [
+            Rgb::new(224, 219, 205),
+            Rgb::new(168, 159, 148),
+            Rgb::new(112, 107, 102),
+            Rgb::new(43, 43, 38),
+        ]),
+        Palette::new("Light", [
+            Rgb::new(0, 181, 129),
+            Rgb::new(0, 154, 113),
+            Rgb::new(0, 105, 74),
+            Rgb::new(0, 79, 59),
+        ]),
+        Palette::new("High contrast", [
+            Rgb::new(255, 255, 255),
+            Rgb::new(170, 170, 170),
+            Rgb::new(85, 85, 85),
+            Rgb::new(0, 0, 0),
+        ]),
+    ]
+}
+
+// Palette files are a list of named sections:
+//
+//     [Sepia]
+//     bg   = #F8E8C8 #D89048 #A82820 #301850
+//     obj0 = #FFFFFF #A0A0A0 #505050 #000000
+//
+// obj0 and obj1 default to the bg colors when omitted.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Palette>, String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(|e| e.to_string())?;
+    parse(&contents)
+}
+
+fn parse(contents: &str) -> Result<Vec<Palette>, String> {
+    let mut palettes: Vec<Palette> = Vec::new();
+    let mut has_obj = (false, false);
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim();
+            palettes.push(Palette::new(name, [Rgb::new(0, 0, 0); 4]));
+            has_obj = (false, false);
+            continue;
+        }
+        let palette = match palettes.last_mut() {
+            Some(palette) => palette,
+            None => return Err(format!("line {}: expected [name]", number + 1))
+        };
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap().trim();
+        let colors = parse_colors(parts.next().unwrap_or(""))
+            .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        match key {
+            "bg" => {
+                palette.bg = colors;
+                if !has_obj.0 { palette.obj0 = colors; }
+                if !has_obj.1 { palette.obj1 = colors; }
+            },
+            "obj0" => {
+                palette.obj0 = colors;
+                has_obj.0 = true;
+            },
+            "obj1" => {
+                palette.obj1 = colors;
+                has_obj.1 = true;
+            },
+            _ => return Err(format!("line {}: unknown key {}", number + 1, key))
+        }
+    }
+    Ok(palettes)
+}
+
+fn parse_colors(value: &str) -> Result<[Rgb; 4], String> {
+    let mut colors = [Rgb::new(0, 0, 0); 4];
+    let mut count = 0;
+    for word in value.split_whitespace() {
+        if count == 4 {
+            return Err("expected 4 colors".into());
+        }
+        let hex = word.trim_start_matches('#');
+        let rgb = match u32::from_str_radix(hex, 16) {
+            Ok(rgb) if hex.len() == 6 => rgb,
+            _ => return Err(format!("invalid color {}", word))
+        };
+        colors[count] = from_hex(rgb);
+        count += 1;
+    }
+    if count != 4 {
+        return Err("expected 4 colors".into());
+    }
+    Ok(colors)
+}
+
+fn from_hex(rgb: u32) -> Rgb {
+    Rgb::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+// The CGB boot ROM's tables for colorizing DMG games. Palettes are 4
+// BGR555 colors, lightest first.
+const CGB_PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000], //  0
+    [0x639F, 0x4279, 0x15B0, 0x04CB], //  1
+    [0x7FFF, 0x6E31, 0x454A, 0x0000], //  2
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000], //  3
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000], //  4
+    [0x7FFF, 0x5294, 0x294A, 0x0000], //  5
+    [0x7FFF, 0x03FF, 0x012F, 0x0000], //  6
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000], //  7
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000], //  8
+    [0x7E74, 0x03FF, 0x0180, 0x0000], //  9
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B], // 10
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000], // 11
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000], // 12
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0], // 13
+    [0x03ED, 0x7FFF, 0x255F, 0x0000], // 14
+    [0x036A, 0x021F, 0x03FF, 0x7FFF], // 15
+    [0x7FFF, 0x01DF, 0x0112, 0x0000], // 16
+    [0x231F, 0x035F, 0x00F2, 0x0009], // 17
+    [0x7FFF, 0x03EA, 0x011F, 0x0000], // 18
+    [0x299F, 0x001A, 0x000C, 0x0000], // 19
+    [0x7FFF, 0x027F, 0x001F, 0x0000], // 20
+    [0x7FFF, 0x03E0, 0x0206, 0x0120], // 21
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00], // 22
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F], // 23
+    [0x7FFF, 0x03FF, 0x001F, 0x0000], // 24
+    [0x03FF, 0x001F, 0x000C, 0x0000], // 25
+    [0x7FFF, 0x033F, 0x0193, 0x0000], // 26
+    [0x0000, 0x4200, 0x037F, 0x7FFF], // 27
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000], // 28
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000], // 29
+];
+
+// first color of a palette in CGB_PALETTES
+const fn palette(index: usize) -> usize {
+    index * 4
+}
+
+// Where each layer's 4 colors start, counted in colors into CGB_PALETTES,
+// as (OBJ0, OBJ1, BG). Three combinations start one color before a
+// palette and run into the next.
+const CGB_COMBINATIONS: [(usize, usize, usize); 51] = [
+    (palette(4), palette(4), palette(29)),          //  0 right+a, default
+    (palette(18), palette(18), palette(18)),        //  1 right
+    (palette(20), palette(20), palette(20)),        //  2
+    (palette(24), palette(24), palette(24)),        //  3 down+a
+    (palette(9), palette(9), palette(9)),           //  4
+    (palette(0), palette(0), palette(0)),           //  5 up
+    (palette(27), palette(27), palette(27)),        //  6 right+b
+    (palette(5), palette(5), palette(5)),           //  7 left+b
+    (palette(12), palette(12), palette(12)),        //  8 down
+    (palette(26), palette(26), palette(26)),        //  9
+    (palette(16), palette(8), palette(8)),          // 10
+    (palette(4), palette(28), palette(28)),         // 11
+    (palette(4), palette(2), palette(2)),           // 12
+    (palette(3), palette(4), palette(4)),           // 13
+    (palette(4), palette(29), palette(29)),         // 14
+    (palette(28), palette(4), palette(28)),         // 15
+    (palette(2), palette(17), palette(2)),          // 16
+    (palette(16), palette(16), palette(8)),         // 17
+    (palette(4), palette(4), palette(7)),           // 18
+    (palette(4), palette(4), palette(18)),          // 19
+    (palette(4), palette(4), palette(20)),          // 20
+    (palette(19), palette(19), palette(9)),         // 21
+    (palette(4) - 1, palette(4) - 1, palette(11)),  // 22
+    (palette(17), palette(17), palette(2)),         // 23
+    (palette(4), palette(4), palette(2)),           // 24
+    (palette(4), palette(4), palette(3)),           // 25
+    (palette(28), palette(28), palette(0)),         // 26
+    (palette(3), palette(3), palette(0)),           // 27
+    (palette(0), palette(0), palette(1)),           // 28 up+b
+    (palette(18), palette(22), palette(18)),        // 29
+    (palette(20), palette(22), palette(20)),        // 30
+    (palette(24), palette(22), palette(24)),        // 31
+    (palette(16), palette(22), palette(8)),         // 32
+    (palette(17), palette(4), palette(13)),         // 33
+    (palette(28) - 1, palette(0), palette(14)),     // 34
+    (palette(28) - 1, palette(4), palette(15)),     // 35
+    (palette(19), palette(22), palette(9)),         // 36
+    (palette(16), palette(28), palette(10)),        // 37
+    (palette(4), palette(23), palette(28)),         // 38
+    (palette(17), palette(22), palette(2)),         // 39
+    (palette(4), palette(0), palette(2)),           // 40 left+a
+    (palette(4), palette(28), palette(3)),          // 41
+    (palette(28), palette(3), palette(0)),          // 42
+    (palette(3), palette(28), palette(4)),          // 43 up+a
+    (palette(21), palette(28), palette(4)),         // 44
+    (palette(3), palette(28), palette(0)),          // 45
+    (palette(25), palette(3), palette(28)),         // 46
+    (palette(0), palette(28), palette(8)),          // 47
+    (palette(4), palette(3), palette(28)),          // 48 left
+    (palette(28), palette(3), palette(6)),          // 49 down+b
+    (palette(4), palette(28), palette(29)),         // 50
+];
+
+// button held during the boot logo, and the combination it selects
+const CGB_MANUAL: &'static [(&'static str, usize)] = &[
+    ("up", 5),
+    ("up+a", 43),
+    ("up+b", 28),
+    ("left", 48),
+    ("left+a", 40),
+    ("left+b", 7),
+    ("down", 8),
+    ("down+a", 3),
+    ("down+b", 49),
+    ("right", 1),
+    ("right+a", 0),
+    ("right+b", 6),
+];
+
+// Title checksums of Nintendo-published DMG games. From index 65 on the
+// checksums repeat and the 4th title letter has to match too.
+const CGB_TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C,
+    0x58, 0xC9, 0x3E, 0x70, 0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA,
+    0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97, 0x4B, 0x90, 0x17, 0x10,
+    0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD,
+    0x5D, 0x6D, 0x67, 0x3F, 0x6B,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF,
+    0x0D, 0xF4, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66,
+    0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+const CGB_FIRST_DUPLICATE: usize = 65;
+const CGB_FOURTH_LETTERS: &'static [u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// combination for each checksum entry
+const CGB_TITLE_COMBINATIONS: [usize; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46,
+    6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+fn cgb_palette(name: &str, combination: usize) -> Palette {
+    let (obj0, obj1, bg) = CGB_COMBINATIONS[combination];
+    let colors = |start: usize| {
+        let color = |i: usize| {
+            let index = start + i;
+            Rgb::from_bgr555(CGB_PALETTES[index / 4][index % 4])
+        };
+        [color(0), color(1), color(2), color(3)]
+    };
+    Palette::with_layers(name, colors(bg), colors(obj0), colors(obj1))
+}
+
+pub fn cgb_manual(combo: &str) -> Option<Palette> {
+    CGB_MANUAL.iter()
+        .find(|&&(name, _)| name == combo)
+        .map(|&(name, combination)| cgb_palette(&format!("CGB {}", name), combination))
+}
+
+// Titles that aren't Nintendo's or aren't in the table get combination 0,
+// the same as checksum 0x00
+pub fn cgb_automatic(checksum: Option<u8>, title: &[u8]) -> Palette {
+    let fourth = title.get(3).cloned().unwrap_or(0);
+    let entry = checksum.and_then(|checksum| {
+        CGB_TITLE_CHECKSUMS.iter().enumerate().position(|(i, &sum)| {
+            sum == checksum && (i < CGB_FIRST_DUPLICATE ||
+                                CGB_FOURTH_LETTERS[i - CGB_FIRST_DUPLICATE] == fourth)
+        })
+    });
+    match entry {
+        Some(i) if i > 0 => cgb_palette("CGB automatic", CGB_TITLE_COMBINATIONS[i]),
+        _ => cgb_palette("CGB default", 0)
+    }
+}